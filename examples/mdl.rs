@@ -16,7 +16,7 @@ use id_tree::{
     TreeBuilder,
 };
 
-use util::coordinates::{convert_coordinates, write_and_convert_channel};
+use util::coordinates::{convert_angles, half_life_coordinate_system, write_channel};
 
 use gltf::{
     Mesh, Model,
@@ -25,14 +25,16 @@ use gltf::{
         Sampler,
     },
     buffer::BufferWriter,
-    export::write_gltf,
+    export::{write_glb, write_gltf},
     material::{
         BaseColorTexture, Image, MagFilter, Material, MaterialData, MinFilter,
         PbrMetallicRoughness, Texture, Wrap,
     },
+    mesh::PrimitiveMode,
     node::{MeshIndex, Node, NodeIndex, Nodes},
-    skin::{Skin, SkinIndex, Skins},
-    transform::{ComponentTransform, quat_from_euler},
+    scene::{Scene, Scenes},
+    skin::{Skin, Skins},
+    transform::{ComponentTransform, CoordinateSystem, euler_from_quat},
     vertex_def,
 };
 
@@ -50,7 +52,7 @@ pub fn export<P: AsRef<Path>>(
     file: &MdlFile,
     output_path: P,
     mut log: Option<&mut String>,
-) -> std::io::Result<()> {
+) -> gltf::error::Result<()> {
     let body_part = file.body_parts.first().unwrap();
     let model = body_part.models.first().unwrap();
 
@@ -66,9 +68,9 @@ pub fn export<P: AsRef<Path>>(
         }
     }
 
-    // Compute bone transforms
+    // Read bone transforms
+    let coordinates = half_life_coordinate_system();
     let mut bone_names = Vec::with_capacity(file.bones.len());
-    let mut local_bone_transforms = Vec::with_capacity(file.bones.len());
     let mut local_bone_component_transforms = Vec::with_capacity(file.bones.len());
     let mut bone_tree = TreeBuilder::new()
         .with_node_capacity(file.bones.len())
@@ -84,63 +86,29 @@ pub fn export<P: AsRef<Path>>(
         };
         let bone_id = bone_tree.insert(id_tree::Node::new(i), behavior).unwrap();
         bone_map.insert(i, bone_id);
-        let bone_pos = Vec3::from_array(convert_coordinates([
-            bone.value[0],
-            bone.value[1],
-            bone.value[2],
-        ]));
-        let bone_angles = Vec3::from_array(convert_coordinates([
-            bone.value[3],
-            bone.value[4],
-            bone.value[5],
-        ]));
-
-        // NOTE: These values have already been converted to GLTF's coordinate system
-        //       Y is yaw, X is pitch, Z is roll
-        let bone_component_transform = ComponentTransform::new(bone_pos, bone_angles);
-        let bone_transform = bone_component_transform.to_mat4();
+        let bone_pos =
+            coordinates.convert_position(Vec3::new(bone.value[0], bone.value[1], bone.value[2]));
+        let bone_rotation = convert_angles(
+            &coordinates,
+            Vec3::new(bone.value[3], bone.value[4], bone.value[5]),
+        );
+        let bone_component_transform =
+            ComponentTransform::new(bone_pos, euler_from_quat(bone_rotation));
 
         bone_names.push(null_terminated_bytes_to_str(&bone.name).to_owned());
-        local_bone_transforms.push(bone_transform);
         local_bone_component_transforms.push(bone_component_transform);
     }
-    let mut world_bone_transforms = vec![Mat4::IDENTITY; file.bones.len()];
-    for node_id in bone_tree
-        .traverse_pre_order_ids(bone_tree.root_node_id().unwrap())
-        .unwrap()
-    {
-        let parent_index = {
-            let mut ancestors = bone_tree.ancestors(&node_id).unwrap();
-            if let Some(node) = ancestors.next() {
-                Some(*node.data())
-            } else {
-                None
-            }
-        };
-
-        let parent_transform = if let Some(parent_index) = parent_index {
-            world_bone_transforms[parent_index]
-        } else {
-            Mat4::IDENTITY
-        };
-        let node_index = *bone_tree.get(&node_id).unwrap().data();
-        let node_transform = *local_bone_transforms.get(node_index).unwrap();
-        let node_world_transform = parent_transform * node_transform;
-
-        world_bone_transforms[node_index] = node_world_transform;
-    }
-    let final_bone_transforms = world_bone_transforms;
 
-    // Compute the inverse bind matrices
-    let inverse_bind_transforms: Vec<_> =
-        final_bone_transforms.iter().map(|x| x.inverse()).collect();
+    // The skin needs the bone nodes, so reserve it now and fill it in later
+    let mut skins = Skins::new();
+    let skin_index = skins.allocate();
 
     // Build nodes
-    let mut nodes = Nodes::new(file.bones.len() + 2);
+    let mut nodes = Nodes::new(file.bones.len() + 1);
     let mut bone_to_node: HashMap<usize, NodeIndex> = HashMap::new();
     let mesh_node = nodes.add_node(Node {
         mesh: Some(MeshIndex(0)),
-        skin: Some(SkinIndex::default()),
+        skin: Some(skin_index),
         ..Default::default()
     });
     for node_id in bone_tree
@@ -149,7 +117,6 @@ pub fn export<P: AsRef<Path>>(
     {
         let bone_index = *bone_tree.get(&node_id).unwrap().data();
         let component_transform = local_bone_component_transforms.get(bone_index).unwrap();
-        let rotation = component_transform.get_rotation_quat();
 
         let mut children = Vec::new();
         for child in bone_tree.children(&node_id).unwrap() {
@@ -158,13 +125,13 @@ pub fn export<P: AsRef<Path>>(
             children.push(*node_index);
         }
 
-        let node_index = nodes.add_node(Node {
+        let mut node = Node {
             name: Some(bone_names[bone_index].clone()),
-            translation: Some(component_transform.translation),
-            rotation: Some(Vec4::from_array(rotation.to_array())),
             children: children,
             ..Default::default()
-        });
+        };
+        node.set_transform(component_transform);
+        let node_index = nodes.add_node(node);
         bone_to_node.insert(bone_index, node_index);
     }
     let skin_root = *bone_to_node
@@ -175,10 +142,23 @@ pub fn export<P: AsRef<Path>>(
                 .data(),
         )
         .unwrap();
-    let scene_root = nodes.add_node(Node {
-        children: vec![mesh_node, skin_root],
-        ..Default::default()
+
+    // Compute bone transforms from the node hierarchy
+    let parents = nodes.parents();
+    let final_bone_transforms: Vec<_> = (0..file.bones.len())
+        .map(|i| {
+            nodes
+                .world_transform_with_parents(&parents, bone_to_node[&i])
+                .unwrap()
+        })
+        .collect();
+
+    let mut scenes = Scenes::new();
+    let scene = scenes.add_scene(Scene {
+        name: None,
+        nodes: vec![mesh_node, skin_root],
     });
+    scenes.set_default_scene(Some(scene));
 
     // Build animations
     let mut animations = Animations::new(file.animations.len());
@@ -203,34 +183,37 @@ pub fn export<P: AsRef<Path>>(
                 }
             }
 
-            // Use the default pose as a baseline
-            let component_transform = &local_bone_component_transforms[bone_animation.target];
-            let translation = component_transform.translation;
-            let rotation = component_transform.rotation;
+            // Use the default pose as a baseline. Channels hold Half-Life
+            // values, so the baseline isn't converted yet either.
+            let bone = &file.bones[bone_animation.target];
+            let translation = Vec3::new(bone.value[0], bone.value[1], bone.value[2]);
+            let rotation = Vec3::new(bone.value[3], bone.value[4], bone.value[5]);
 
             let target_node = *bone_to_node.get(&target_bone).unwrap();
             if process_animation(
                 &mut animation,
                 &mut buffer_writer,
+                &coordinates,
                 translation,
                 AnimationTarget::Translation,
                 &translate_animations,
                 &bone_animation.channels,
                 target_node,
                 gs_animation.fps,
-            ) {
+            )? {
                 should_add = true;
             }
             if process_animation(
                 &mut animation,
                 &mut buffer_writer,
+                &coordinates,
                 rotation,
                 AnimationTarget::Rotation,
                 &rotation_animations,
                 &bone_animation.channels,
                 target_node,
                 gs_animation.fps,
-            ) {
+            )? {
                 should_add = true;
             }
         }
@@ -253,6 +236,10 @@ pub fn export<P: AsRef<Path>>(
 
                 let index_start = indices.len();
                 let mut vertex_map = HashMap::new();
+                // Sequences are expanded to a single triangle list instead of
+                // being written as TriangleStrip/TriangleFan primitives. Each
+                // sequence would need its own primitive (and draw call), and
+                // MDL winds them the opposite way to glTF.
                 for sequence in &mdl_mesh.sequences {
                     match sequence.ty {
                         MdlMeshSequenceType::TriangleStrip => {
@@ -270,6 +257,7 @@ pub fn export<P: AsRef<Path>>(
                             }
                             process_indexed_triangles(
                                 model,
+                                &coordinates,
                                 texture_width,
                                 texture_height,
                                 &triverts,
@@ -277,7 +265,7 @@ pub fn export<P: AsRef<Path>>(
                                 &mut indices,
                                 &mut vertices,
                                 &mut vertex_map,
-                            );
+                            )?;
                         }
                         MdlMeshSequenceType::TriangleFan => {
                             let mut triverts = Vec::new();
@@ -288,6 +276,7 @@ pub fn export<P: AsRef<Path>>(
                             }
                             process_indexed_triangles(
                                 model,
+                                &coordinates,
                                 texture_width,
                                 texture_height,
                                 &triverts,
@@ -295,15 +284,17 @@ pub fn export<P: AsRef<Path>>(
                                 &mut indices,
                                 &mut vertices,
                                 &mut vertex_map,
-                            );
+                            )?;
                         }
                     }
                 }
                 let index_end = indices.len();
 
                 meshes.push(Mesh {
-                    texture_index: mdl_mesh.skin_ref as usize,
-                    indices_range: index_start..index_end,
+                    texture_index: Some(mdl_mesh.skin_ref as usize),
+                    indices_range: Some(index_start..index_end),
+                    vertex_range: None,
+                    mode: PrimitiveMode::Triangles,
                 })
             }
             (meshes, indices, vertices)
@@ -312,11 +303,15 @@ pub fn export<P: AsRef<Path>>(
             indices,
             vertices,
             meshes,
+            morph_targets: Vec::new(),
         }
     };
 
-    let inverse_bind_matrices_pair =
-        buffer_writer.create_view_and_accessor(&inverse_bind_transforms, None);
+    let path = output_path.as_ref();
+    let is_glb = path
+        .extension()
+        .map(|x| x.eq_ignore_ascii_case("glb"))
+        .unwrap_or(false);
 
     // Create materials, textures, and images
     let mut material_data = MaterialData::new();
@@ -327,11 +322,20 @@ pub fn export<P: AsRef<Path>>(
         wrap_t: Wrap::MirroredRepeat,
     });
     for texture in &file.textures {
-        let image = material_data.add_images(Image {
-            uri: format!("{}.png", texture.name),
-        });
+        // GLB files embed their images so they're a single file
+        let image = if is_glb {
+            let mut png = Vec::new();
+            texture
+                .image_data
+                .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+                .unwrap();
+            Image::from_data(&mut buffer_writer, &png, "image/png")
+        } else {
+            Image::from_uri(format!("{}.png", texture.name))
+        };
+        let image = material_data.add_images(image);
         let texture = material_data.add_texture(Texture {
-            sampler,
+            sampler: Some(sampler),
             source: image,
         });
         material_data.add_material(Material {
@@ -341,65 +345,73 @@ pub fn export<P: AsRef<Path>>(
                 roughness_factor: 1.0,
                 ..Default::default()
             },
+            ..Default::default()
         });
     }
 
-    // Build skin
-    let mut skins = Skins::new();
+    // Build skin, binding it to the pose the bone nodes are in
     let skin = {
         let mut joints = Vec::with_capacity(file.bones.len());
         for i in 0..file.bones.len() {
             let node = *bone_to_node.get(&i).unwrap();
             joints.push(node);
         }
-        Skin {
-            inverse_bind_matrices: inverse_bind_matrices_pair.accessor,
-            joints,
-        }
+        Skin::from_bind_pose(joints, &nodes, &mut buffer_writer)?
     };
-    let skin_index = skins.add_skin(skin);
-    // TODO: Need a way to update the skin so that we can do this earlier
-    //       instead of hard coding this.
-    assert_eq!(skin_index.0, 0);
-
-    let buffer_name = "data.bin";
-    let gltf_text = write_gltf(
-        gltf::document::BufferSource::Uri(buffer_name),
-        &mut buffer_writer,
-        &[converted_model],
-        &material_data,
-        scene_root,
-        &nodes,
-        &skins,
-        &animations,
-    );
-
-    let path = output_path.as_ref();
-    let data_path = if let Some(parent_path) = path.parent() {
-        let mut data_path = parent_path.to_owned();
-        data_path.push(buffer_name);
-        data_path
+    skins
+        .update(skin_index, skin)
+        .expect("The skin was allocated before the nodes were built");
+
+    if is_glb {
+        let glb_data = write_glb(
+            &mut buffer_writer,
+            &[converted_model],
+            &material_data,
+            &scenes,
+            &nodes,
+            &skins,
+            &animations,
+        )?;
+        std::fs::write(path, glb_data)?;
     } else {
-        PathBuf::from(buffer_name)
-    };
+        let buffer_name = "data.bin";
+        let gltf_text = write_gltf(
+            gltf::document::BufferSource::Uri(buffer_name),
+            &mut buffer_writer,
+            &[converted_model],
+            &material_data,
+            &scenes,
+            &nodes,
+            &skins,
+            &animations,
+        )?;
+
+        let data_path = if let Some(parent_path) = path.parent() {
+            let mut data_path = parent_path.to_owned();
+            data_path.push(buffer_name);
+            data_path
+        } else {
+            PathBuf::from(buffer_name)
+        };
 
-    std::fs::write(path, gltf_text)?;
-    std::fs::write(data_path, buffer_writer.to_inner())?;
+        std::fs::write(path, gltf_text)?;
+        std::fs::write(data_path, buffer_writer.to_inner())?;
 
-    // Write textures
-    let mut texture_path = if let Some(parent_path) = path.parent() {
-        let mut data_path = parent_path.to_owned();
-        data_path.push("something");
-        data_path
-    } else {
-        PathBuf::from("something")
-    };
-    for texture in &file.textures {
-        texture_path.set_file_name(format!("{}.png", texture.name));
-        texture
-            .image_data
-            .save_with_format(&texture_path, image::ImageFormat::Png)
-            .unwrap();
+        // Write textures
+        let mut texture_path = if let Some(parent_path) = path.parent() {
+            let mut data_path = parent_path.to_owned();
+            data_path.push("something");
+            data_path
+        } else {
+            PathBuf::from("something")
+        };
+        for texture in &file.textures {
+            texture_path.set_file_name(format!("{}.png", texture.name));
+            texture
+                .image_data
+                .save_with_format(&texture_path, image::ImageFormat::Png)
+                .unwrap();
+        }
     }
 
     Ok(())
@@ -407,6 +419,7 @@ pub fn export<P: AsRef<Path>>(
 
 fn process_indexed_triangles(
     model: &MdlModel,
+    coordinates: &CoordinateSystem,
     texture_width: f32,
     texture_height: f32,
     triverts: &[MdlMeshVertex],
@@ -414,30 +427,35 @@ fn process_indexed_triangles(
     indices: &mut Vec<u32>,
     vertices: &mut Vec<SkinnedVertex>,
     vertex_map: &mut HashMap<MdlMeshVertex, usize>,
-) {
-    assert!(
-        triverts.len() % 3 == 0,
-        "Vertices are not a multiple of 3: {}",
-        triverts.len()
-    );
+) -> gltf::error::Result<()> {
+    if triverts.len() % 3 != 0 {
+        return Err(gltf::error::Error::SpecViolation(format!(
+            "Vertices are not a multiple of 3: {}",
+            triverts.len()
+        )));
+    }
 
     let mut process_trivert = |trivert| {
         let index = if let Some(index) = vertex_map.get(trivert) {
             *index
         } else {
-            let pos = convert_coordinates(model.vertices[trivert.vertex_index as usize]);
-            let normal = convert_coordinates(model.normals[trivert.normal_index as usize]);
+            let pos = coordinates.convert_position(Vec3::from_array(
+                model.vertices[trivert.vertex_index as usize],
+            ));
+            let normal = coordinates.convert_direction(Vec3::from_array(
+                model.normals[trivert.normal_index as usize],
+            ));
 
             let bone_index = model.vertex_bone_indices[trivert.vertex_index as usize];
             let pos = {
                 let bone = world_bone_transforms[bone_index as usize];
-                let pos = bone * Vec4::new(pos[0], pos[1], pos[2], 1.0);
+                let pos = bone * pos.extend(1.0);
                 let pos = pos.xyz().to_array();
                 pos
             };
             let normal = {
                 let bone = world_bone_transforms[bone_index as usize];
-                let normal = bone * Vec4::new(normal[0], normal[1], normal[2], 0.0);
+                let normal = bone * normal.extend(0.0);
                 let normal = normal.xyz().normalize().to_array();
                 normal
             };
@@ -470,34 +488,38 @@ fn process_indexed_triangles(
     for trivert in triverts {
         process_trivert(trivert);
     }
+
+    Ok(())
 }
 
 fn process_animation(
     animation: &mut Animation,
     buffer_writer: &mut BufferWriter,
+    coordinates: &CoordinateSystem,
     mut base: Vec3,
     target: AnimationTarget,
-    animations: &[(VectorChannel, usize)],
+    vector_channels: &[(VectorChannel, usize)],
     channels: &[BoneChannelAnimation],
     target_node: NodeIndex,
     fps: f32,
-) -> bool {
-    if !animations.is_empty() {
-        let animation_length = channels[animations.first().unwrap().1].keyframes.len();
-        assert!(
-            animations
-                .iter()
-                .all(|(_, index)| channels[*index].keyframes.len() == animation_length)
-        );
+) -> gltf::error::Result<bool> {
+    if !vector_channels.is_empty() {
+        let animation_length = channels[vector_channels.first().unwrap().1].keyframes.len();
+        if !vector_channels
+            .iter()
+            .all(|(_, index)| channels[*index].keyframes.len() == animation_length)
+        {
+            return Err(gltf::error::Error::SpecViolation(
+                "Animation channels have different keyframe counts".to_owned(),
+            ));
+        }
 
         let mut new_keyframes = Vec::with_capacity(animation_length);
         for i in 0..animation_length {
-            for (vec_channel, channel_index) in animations {
+            for (vec_channel, channel_index) in vector_channels {
                 let channel = &channels[*channel_index];
                 let value = channel.keyframes[i];
-                // NOTE: We are converting from Half-Life coordinates to GLTF
-                //       See convert_coordinates for more details.
-                write_and_convert_channel(&mut base, *vec_channel, value);
+                write_channel(&mut base, *vec_channel, value);
             }
             new_keyframes.push(base);
         }
@@ -508,25 +530,27 @@ fn process_animation(
             timestamps.push(i as f32 * seconds_per_frame);
         }
 
-        // TODO: Consolodate timestamps
-        let input = {
-            let pair = buffer_writer.create_view_and_accessor_with_min_max(&timestamps, None);
-            pair.accessor
-        };
+        let input = buffer_writer.create_animation_input(&timestamps);
 
         let output = match target {
             AnimationTarget::Translation => {
-                let pair = buffer_writer.create_view_and_accessor(&new_keyframes, None);
+                let positions: Vec<_> = new_keyframes
+                    .iter()
+                    .map(|x| coordinates.convert_position(*x))
+                    .collect();
+                let pair = buffer_writer.create_view_and_accessor(&positions, None);
                 pair.accessor
             }
             AnimationTarget::Rotation => {
                 let quats: Vec<_> = new_keyframes
                     .iter()
-                    .map(|x| Vec4::from_array(quat_from_euler(*x).to_array()))
+                    .map(|x| Vec4::from_array(convert_angles(coordinates, *x).to_array()))
                     .collect();
                 let pair = buffer_writer.create_view_and_accessor(&quats, None);
                 pair.accessor
             }
+            AnimationTarget::Scale => unreachable!("MDL files don't animate scale"),
+            AnimationTarget::Weights => unreachable!("MDL files don't have morph targets"),
         };
 
         let sampler = animation.add_sampler(Sampler {
@@ -543,9 +567,9 @@ fn process_animation(
             },
         });
 
-        true
+        Ok(true)
     } else {
-        false
+        Ok(false)
     }
 }
 
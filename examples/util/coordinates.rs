@@ -1,4 +1,5 @@
-use glam::Vec3;
+use glam::{EulerRot, Quat, Vec3};
+use gltf::transform::{Axis, CoordinateSystem, Handedness};
 use gsparser::mdl::VectorChannel;
 
 // Half-Life's coordinate system uses:
@@ -6,22 +7,20 @@ use gsparser::mdl::VectorChannel;
 //    Y is left
 //    Z is up
 //    (https://github.com/malortie/assimp/wiki/MDL:-Half-Life-1-file-format#notes)
-// GLTF's coordinate system uses:
-//    X is left (-X is right)
-//    Y is up
-//    Z is forward
-//    (https://registry.khronos.org/glTF/specs/2.0/glTF-2.0.html#coordinate-system-and-units)
-pub fn convert_coordinates<T: Copy>(half_life_xyz: [T; 3]) -> [T; 3] {
-    [half_life_xyz[1], half_life_xyz[2], half_life_xyz[0]]
+// CoordinateSystem converts it to GLTF's, where HL (x, y, z) becomes (y, z, x)
+pub fn half_life_coordinate_system() -> CoordinateSystem {
+    CoordinateSystem::new(Axis::PositiveZ, Axis::PositiveX, Handedness::Right, 1.0).unwrap()
 }
 
-pub fn write_and_convert_channel(base: &mut Vec3, channel: VectorChannel, value: f32) {
+// Half-Life bone angles are roll (X), pitch (Y) and yaw (Z), applied yaw first
+pub fn convert_angles(system: &CoordinateSystem, angles: Vec3) -> Quat {
+    system.convert_euler(EulerRot::ZYX, Vec3::new(angles.z, angles.y, angles.x))
+}
+
+pub fn write_channel(base: &mut Vec3, channel: VectorChannel, value: f32) {
     match channel {
-        // HL X => GLTF Z
-        VectorChannel::X => base.z = value,
-        // HL Y => GLTF X
-        VectorChannel::Y => base.x = value,
-        // HL Z => GLTF Y
-        VectorChannel::Z => base.y = value,
+        VectorChannel::X => base.x = value,
+        VectorChannel::Y => base.y = value,
+        VectorChannel::Z => base.z = value,
     }
 }
@@ -1,7 +1,9 @@
-use serde::Serialize;
+use glam::{Quat, Vec3};
+use serde::{Deserialize, Serialize};
 
 use crate::{
     enum_with_str,
+    error::{Error, Result},
     storage::{Storage, StorageIndex},
 };
 
@@ -11,29 +13,36 @@ pub type ChannelIndex = StorageIndex<Channel>;
 pub type SamplerIndex = StorageIndex<Sampler>;
 pub type AnimationIndex = StorageIndex<Animation>;
 
-#[derive(Clone, Default, Serialize)]
+#[derive(Clone, Default, Serialize, Deserialize)]
 pub struct Animation {
-    channels: Storage<Channel>,
+    pub(crate) channels: Storage<Channel>,
+    #[serde(default)]
     name: String,
-    samplers: Storage<Sampler>,
+    pub(crate) samplers: Storage<Sampler>,
 }
 
-#[derive(Clone, Default, Serialize)]
+#[derive(Clone, Default, Serialize, Deserialize)]
 pub struct Channel {
     pub sampler: SamplerIndex,
     pub target: ChannelTarget,
 }
 
-#[derive(Clone, Default, Serialize)]
+#[derive(Clone, Default, Serialize, Deserialize)]
 pub struct ChannelTarget {
     pub node: NodeIndex,
     pub path: AnimationTarget,
 }
 
-enum_with_str!(AnimationInterpolation { Linear: "LINEAR" });
+enum_with_str!(AnimationInterpolation {
+    Linear: "LINEAR",
+    Step: "STEP",
+    CubicSpline: "CUBICSPLINE",
+});
 enum_with_str!(AnimationTarget {
     Translation: "translation",
     Rotation: "rotation",
+    Scale: "scale",
+    Weights: "weights",
 });
 
 impl Default for AnimationInterpolation {
@@ -47,17 +56,124 @@ impl Default for AnimationTarget {
     }
 }
 
-#[derive(Clone, Default, Serialize)]
+#[derive(Clone, Default, Serialize, Deserialize)]
 pub struct Sampler {
     pub input: AccessorIndex,
+    #[serde(default)]
     pub interpolation: AnimationInterpolation,
     pub output: AccessorIndex,
 }
 
-#[derive(Clone, Default, Serialize)]
+/// A keyframe of a `CUBICSPLINE` sampler. The tangents are scaled by the
+/// duration of the adjacent segments when the curve is evaluated.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct CubicSplineKeyframe<T> {
+    pub in_tangent: T,
+    pub value: T,
+    pub out_tangent: T,
+}
+
+/// Flattens keyframes into the in-tangent, value, out-tangent order used
+/// by the output accessor of a `CUBICSPLINE` sampler.
+pub fn cubic_spline_output<T: Copy>(keyframes: &[CubicSplineKeyframe<T>]) -> Vec<T> {
+    let mut output = Vec::with_capacity(keyframes.len() * 3);
+    for keyframe in keyframes {
+        output.push(keyframe.in_tangent);
+        output.push(keyframe.value);
+        output.push(keyframe.out_tangent);
+    }
+    output
+}
+
+/// Removes keyframes of a `LINEAR` translation or scale curve that are
+/// within `tolerance` (Euclidean distance) of the curve without them. The
+/// first and last keyframes are always kept.
+pub fn simplify_vec3_keyframes(
+    timestamps: &[f32],
+    values: &[Vec3],
+    tolerance: f32,
+) -> Result<(Vec<f32>, Vec<Vec3>)> {
+    simplify_keyframes(timestamps, values, tolerance, Vec3::lerp, Vec3::distance)
+}
+
+/// Like `simplify_vec3_keyframes`, but for rotations. The tolerance is an
+/// angle in radians and the curve is interpolated with slerp.
+pub fn simplify_quat_keyframes(
+    timestamps: &[f32],
+    values: &[Quat],
+    tolerance: f32,
+) -> Result<(Vec<f32>, Vec<Quat>)> {
+    simplify_keyframes(
+        timestamps,
+        values,
+        tolerance,
+        Quat::slerp,
+        Quat::angle_between,
+    )
+}
+
+fn simplify_keyframes<T: Copy>(
+    timestamps: &[f32],
+    values: &[T],
+    tolerance: f32,
+    interpolate: impl Fn(T, T, f32) -> T,
+    error: impl Fn(T, T) -> f32,
+) -> Result<(Vec<f32>, Vec<T>)> {
+    if timestamps.len() != values.len() {
+        return Err(Error::SpecViolation(format!(
+            "There are {} timestamps but {} values",
+            timestamps.len(),
+            values.len()
+        )));
+    }
+    if timestamps.len() <= 2 {
+        return Ok((timestamps.to_vec(), values.to_vec()));
+    }
+
+    // Ramer-Douglas-Peucker: keep the keyframe furthest from the segment
+    // between two kept keyframes until every skipped keyframe is close enough
+    let mut keep = vec![false; timestamps.len()];
+    keep[0] = true;
+    keep[timestamps.len() - 1] = true;
+    let mut segments = vec![(0, timestamps.len() - 1)];
+    while let Some((start, end)) = segments.pop() {
+        let duration = timestamps[end] - timestamps[start];
+        let mut furthest = None;
+        let mut furthest_error = tolerance;
+        for i in start + 1..end {
+            let s = if duration > 0.0 {
+                (timestamps[i] - timestamps[start]) / duration
+            } else {
+                0.0
+            };
+            let expected = interpolate(values[start], values[end], s);
+            let error = error(expected, values[i]);
+            if error > furthest_error {
+                furthest = Some(i);
+                furthest_error = error;
+            }
+        }
+        if let Some(i) = furthest {
+            keep[i] = true;
+            segments.push((start, i));
+            segments.push((i, end));
+        }
+    }
+
+    let (timestamps, values) = timestamps
+        .iter()
+        .zip(values)
+        .zip(keep)
+        .filter(|(_, keep)| *keep)
+        .map(|((timestamp, value), _)| (*timestamp, *value))
+        .unzip();
+    Ok((timestamps, values))
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
 #[serde(transparent)]
 pub struct Animations {
-    animations: Storage<Animation>,
+    pub(crate) animations: Storage<Animation>,
 }
 
 impl Animation {
@@ -76,6 +192,38 @@ impl Animation {
     pub fn add_channel(&mut self, channel: Channel) -> ChannelIndex {
         self.channels.allocate_with(channel)
     }
+
+    pub fn allocate_sampler(&mut self) -> SamplerIndex {
+        self.samplers.allocate()
+    }
+
+    pub fn update_sampler(&mut self, index: SamplerIndex, sampler: Sampler) -> Option<()> {
+        self.samplers.update(index, sampler)
+    }
+
+    pub fn get_sampler(&self, index: SamplerIndex) -> Option<&Sampler> {
+        self.samplers.get(index)
+    }
+
+    pub fn get_sampler_mut(&mut self, index: SamplerIndex) -> Option<&mut Sampler> {
+        self.samplers.get_mut(index)
+    }
+
+    pub fn allocate_channel(&mut self) -> ChannelIndex {
+        self.channels.allocate()
+    }
+
+    pub fn update_channel(&mut self, index: ChannelIndex, channel: Channel) -> Option<()> {
+        self.channels.update(index, channel)
+    }
+
+    pub fn get_channel(&self, index: ChannelIndex) -> Option<&Channel> {
+        self.channels.get(index)
+    }
+
+    pub fn get_channel_mut(&mut self, index: ChannelIndex) -> Option<&mut Channel> {
+        self.channels.get_mut(index)
+    }
 }
 
 impl Animations {
@@ -89,11 +237,99 @@ impl Animations {
         self.animations.allocate_with(animation)
     }
 
+    pub fn allocate(&mut self) -> AnimationIndex {
+        self.animations.allocate()
+    }
+
+    pub fn update(&mut self, index: AnimationIndex, animation: Animation) -> Option<()> {
+        self.animations.update(index, animation)
+    }
+
+    pub fn get(&self, index: AnimationIndex) -> Option<&Animation> {
+        self.animations.get(index)
+    }
+
+    pub fn get_mut(&mut self, index: AnimationIndex) -> Option<&mut Animation> {
+        self.animations.get_mut(index)
+    }
+
     pub fn is_empty(&self) -> bool {
         self.animations.is_empty()
     }
 
-    pub fn write_animations(&self) -> String {
-        serde_json::to_string_pretty(&self.animations).unwrap()
+    pub fn write_animations(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(&self.animations)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vec3_keyframes(xs: &[f32]) -> Vec<Vec3> {
+        xs.iter().map(|x| Vec3::new(*x, 0.0, 0.0)).collect()
+    }
+
+    #[test]
+    fn simplify_splits_at_furthest_keyframe() {
+        let timestamps = [0.0, 1.0, 2.0, 3.0, 4.0];
+        let values = vec3_keyframes(&[0.0, 1.0, 2.0, 1.0, 0.0]);
+        let (timestamps, values) = simplify_vec3_keyframes(&timestamps, &values, 0.1).unwrap();
+        assert_eq!(timestamps, [0.0, 2.0, 4.0]);
+        assert_eq!(values, vec3_keyframes(&[0.0, 2.0, 0.0]));
+    }
+
+    #[test]
+    fn simplify_keeps_keyframes_outside_tolerance() {
+        let timestamps = [0.0, 1.0, 2.0];
+        let values = vec3_keyframes(&[0.0, 0.5, 1.0]);
+        let (simplified, _) = simplify_vec3_keyframes(&timestamps, &values, 0.1).unwrap();
+        assert_eq!(simplified, [0.0, 2.0]);
+
+        let values = vec3_keyframes(&[0.0, 0.75, 1.0]);
+        let (simplified, _) = simplify_vec3_keyframes(&timestamps, &values, 0.1).unwrap();
+        assert_eq!(simplified, timestamps);
+    }
+
+    #[test]
+    fn simplify_zero_duration_segment() {
+        // A step at t = 1 leaves a segment with equal start and end times
+        let timestamps = [0.0, 1.0, 1.0, 1.0, 2.0];
+        let values = vec3_keyframes(&[0.0, 0.0, 0.0, 5.0, 5.0]);
+        let (timestamps, values) = simplify_vec3_keyframes(&timestamps, &values, 0.1).unwrap();
+        assert_eq!(timestamps, [0.0, 1.0, 1.0, 2.0]);
+        assert_eq!(values, vec3_keyframes(&[0.0, 0.0, 5.0, 5.0]));
+
+        let timestamps = [0.0, 1.0, 1.0, 1.0, 2.0];
+        let values = vec3_keyframes(&[0.0, 0.0, 3.0, 5.0, 5.0]);
+        let (simplified, values) = simplify_vec3_keyframes(&timestamps, &values, 0.1).unwrap();
+        assert_eq!(simplified, timestamps);
+        assert!(values.iter().all(|x| x.is_finite()));
+    }
+
+    #[test]
+    fn simplify_quat_sign_flips() {
+        // q and -q are the same rotation, so flipping signs adds no error
+        let q = Quat::from_rotation_y(1.0);
+        let timestamps = [0.0, 1.0, 2.0, 3.0];
+        let values = [q, -q, q, -q];
+        let (timestamps, values) = simplify_quat_keyframes(&timestamps, &values, 1e-3).unwrap();
+        assert_eq!(timestamps, [0.0, 3.0]);
+        assert_eq!(values, [q, -q]);
+
+        let timestamps = [0.0, 1.0, 2.0];
+        let values = [
+            Quat::IDENTITY,
+            -Quat::from_rotation_y(0.5),
+            Quat::from_rotation_y(1.0),
+        ];
+        let (simplified, _) = simplify_quat_keyframes(&timestamps, &values, 1e-3).unwrap();
+        assert_eq!(simplified, [0.0, 2.0]);
+    }
+
+    #[test]
+    fn simplify_mismatched_lengths() {
+        let values = vec3_keyframes(&[0.0, 1.0]);
+        assert!(simplify_vec3_keyframes(&[0.0], &values, 0.1).is_err());
     }
 }
@@ -1,10 +1,15 @@
+use std::collections::HashMap;
+
 use glam::{Mat4, Vec3, Vec4};
-use serde::{Serialize, Serializer, ser::SerializeStruct};
-use serde_repr::Serialize_repr;
+use serde::{
+    Deserialize, Deserializer, Serialize, Serializer, ser::Error as _, ser::SerializeStruct,
+};
+use serde_repr::{Deserialize_repr, Serialize_repr};
 use serde_with::skip_serializing_none;
 
 use crate::{
     enum_with_str,
+    error::{Error, Result},
     storage::{Storage, StorageIndex},
 };
 
@@ -34,23 +39,44 @@ pub struct MinMax<T> {
 }
 
 impl Serialize for MinMax<String> {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
     where
         S: Serializer,
     {
         let mut s = serializer.serialize_struct("MinMax", 2)?;
         s.serialize_field(
             "min",
-            &serde_json::value::RawValue::from_string(self.min.clone()).unwrap(),
+            &serde_json::value::RawValue::from_string(self.min.clone())
+                .map_err(S::Error::custom)?,
         )?;
         s.serialize_field(
             "max",
-            &serde_json::value::RawValue::from_string(self.max.clone()).unwrap(),
+            &serde_json::value::RawValue::from_string(self.max.clone())
+                .map_err(S::Error::custom)?,
         )?;
         s.end()
     }
 }
 
+impl<'de> Deserialize<'de> for MinMax<String> {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct RawMinMax {
+            min: serde_json::Value,
+            max: serde_json::Value,
+        }
+
+        let raw = RawMinMax::deserialize(deserializer)?;
+        Ok(Self {
+            min: raw.min.to_string(),
+            max: raw.max.to_string(),
+        })
+    }
+}
+
 impl<T: BufferTypeMinMax> BufferTypeEx for T {
     fn find_min_max(data: &[Self]) -> (Self, Self) {
         let mut max = T::MIN;
@@ -79,14 +105,22 @@ impl BufferViewAndAccessorPair {
 }
 
 #[skip_serializing_none]
-#[derive(Debug, Default, Serialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct BufferWriter {
     #[serde(skip)]
     buffer: Vec<u8>,
-    #[serde(rename = "bufferViews")]
-    views: Storage<BufferView>,
-    accessors: Storage<Accessor>,
+    #[serde(
+        rename = "bufferViews",
+        default,
+        skip_serializing_if = "Storage::is_empty"
+    )]
+    pub(crate) views: Storage<BufferView>,
+    #[serde(default, skip_serializing_if = "Storage::is_empty")]
+    pub(crate) accessors: Storage<Accessor>,
+    // Keyed by the bits of the timestamps so identical inputs are only written once
+    #[serde(skip)]
+    animation_inputs: HashMap<Vec<u32>, AccessorIndex>,
 }
 
 impl BufferWriter {
@@ -95,7 +129,22 @@ impl BufferWriter {
             buffer: Vec::new(),
             views: Storage::new(),
             accessors: Storage::new(),
+            animation_inputs: HashMap::new(),
+        }
+    }
+
+    /// Returns an animation sampler input accessor for `timestamps`, reusing
+    /// the accessor written by any previous call with the same timestamps.
+    pub fn create_animation_input(&mut self, timestamps: &[f32]) -> AccessorIndex {
+        let key = timestamps.iter().map(|x| x.to_bits()).collect();
+        if let Some(accessor) = self.animation_inputs.get(&key) {
+            return *accessor;
         }
+        let accessor = self
+            .create_view_and_accessor_with_min_max(timestamps, None)
+            .accessor;
+        self.animation_inputs.insert(key, accessor);
+        accessor
     }
 
     pub fn create_view<T: BufferType + Copy>(
@@ -103,13 +152,29 @@ impl BufferWriter {
         data: &[T],
         target: Option<BufferViewTarget>,
     ) -> BufferViewIndex {
+        self.write_view(data, target, T::stride())
+    }
+
+    fn write_view<T: BufferType + Copy>(
+        &mut self,
+        data: &[T],
+        target: Option<BufferViewTarget>,
+        stride: Option<usize>,
+    ) -> BufferViewIndex {
+        // Accessors must start on a multiple of their component size, and
+        // vertex attributes must also be aligned to 4 bytes.
+        let alignment = match target {
+            Some(BufferViewTarget::ArrayBuffer) => T::COMPONENT_TY.size().max(4),
+            _ => T::COMPONENT_TY.size(),
+        };
+        self.align_to(alignment);
+
         let offset = self.buffer.len();
         for item in data {
             let mut bytes = item.to_bytes();
             self.buffer.append(&mut bytes);
         }
         let byte_len = self.buffer.len() - offset;
-        let stride = T::stride();
         let index = self.views.allocate_with(BufferView {
             buffer: 0,
             byte_offset: offset,
@@ -120,6 +185,27 @@ impl BufferWriter {
         index
     }
 
+    /// Creates a view over raw bytes that hold several interleaved
+    /// elements, e.g. the fields of a vertex struct.
+    pub fn create_strided_view(
+        &mut self,
+        data: &[u8],
+        stride: usize,
+        target: Option<BufferViewTarget>,
+    ) -> BufferViewIndex {
+        self.align_to(4);
+
+        let offset = self.buffer.len();
+        self.buffer.extend_from_slice(data);
+        self.views.allocate_with(BufferView {
+            buffer: 0,
+            byte_offset: offset,
+            byte_len: data.len(),
+            stride: Some(stride),
+            target,
+        })
+    }
+
     pub fn create_accessor<T: BufferType + Copy>(
         &mut self,
         view_index: BufferViewIndex,
@@ -127,12 +213,14 @@ impl BufferWriter {
         len: usize,
     ) -> AccessorIndex {
         self.accessors.allocate_with(Accessor {
-            buffer_view: view_index.0,
-            byte_offset,
+            buffer_view: Some(view_index.0),
+            byte_offset: Some(byte_offset),
             count: len,
             component_ty: T::COMPONENT_TY,
             ty: T::TY,
+            normalized: false,
             min_max: None,
+            sparse: None,
         })
     }
 
@@ -144,18 +232,121 @@ impl BufferWriter {
         min_max: MinMax<T>,
     ) -> AccessorIndex {
         self.accessors.allocate_with(Accessor {
-            buffer_view: view_index.0,
-            byte_offset,
+            buffer_view: Some(view_index.0),
+            byte_offset: Some(byte_offset),
             count: len,
             component_ty: T::COMPONENT_TY,
             ty: T::TY,
+            normalized: false,
             min_max: Some(MinMax {
                 min: min_max.min.write_value(),
                 max: min_max.max.write_value(),
             }),
+            sparse: None,
         })
     }
 
+    /// Creates an accessor of `len` elements where only the elements at
+    /// `indices` are stored, with the rest coming from `base` (or zeros if
+    /// there is no base). `indices` must be strictly increasing.
+    pub fn create_sparse_accessor_with_min_max<T: BufferTypeMinMax + Copy + Default>(
+        &mut self,
+        base: Option<SparseBase<T>>,
+        len: usize,
+        indices: &[u32],
+        values: &[T],
+    ) -> Result<AccessorIndex> {
+        if indices.len() != values.len() {
+            return Err(Error::SpecViolation(format!(
+                "Sparse accessor has {} indices but {} values",
+                indices.len(),
+                values.len()
+            )));
+        }
+        if indices.is_empty() {
+            return Err(Error::SpecViolation(
+                "Sparse accessor must have at least one value".to_owned(),
+            ));
+        }
+        if !indices.windows(2).all(|x| x[0] < x[1]) {
+            return Err(Error::SpecViolation(
+                "Sparse accessor indices must be strictly increasing".to_owned(),
+            ));
+        }
+        let last_index = *indices.last().unwrap() as usize;
+        if last_index >= len {
+            return Err(Error::InvalidIndex {
+                kind: "sparse accessor",
+                index: last_index,
+                len,
+            });
+        }
+        if let Some(base) = &base
+            && base.data.len() != len
+        {
+            return Err(Error::SpecViolation(format!(
+                "Sparse accessor has {} elements but its base has {}",
+                len,
+                base.data.len()
+            )));
+        }
+
+        // Compute the bounds over the values after substitution
+        let mut max = T::MIN;
+        let mut min = T::MAX;
+        let mut sparse_values = indices.iter().zip(values).peekable();
+        for i in 0..len {
+            let value = match sparse_values.next_if(|(index, _)| **index as usize == i) {
+                Some((_, value)) => *value,
+                None => base.as_ref().map(|x| x.data[i]).unwrap_or_default(),
+            };
+            max = value.data_max(&max);
+            min = value.data_min(&min);
+        }
+
+        // Sparse index and value views can't have a target or a stride
+        let (indices_view, indices_component_ty) = if last_index <= u8::MAX as usize {
+            let indices: Vec<_> = indices.iter().map(|x| *x as u8).collect();
+            (self.write_view(&indices, None, None), u8::COMPONENT_TY)
+        } else if last_index <= u16::MAX as usize {
+            let indices: Vec<_> = indices.iter().map(|x| *x as u16).collect();
+            (self.write_view(&indices, None, None), u16::COMPONENT_TY)
+        } else {
+            (self.write_view(indices, None, None), u32::COMPONENT_TY)
+        };
+        let values_view = self.write_view(values, None, None);
+
+        Ok(self.accessors.allocate_with(Accessor {
+            buffer_view: base.as_ref().map(|x| x.view.0),
+            byte_offset: base.as_ref().map(|x| x.byte_offset),
+            count: len,
+            component_ty: T::COMPONENT_TY,
+            ty: T::TY,
+            normalized: false,
+            min_max: Some(MinMax {
+                min: min.write_value(),
+                max: max.write_value(),
+            }),
+            sparse: Some(AccessorSparse {
+                count: indices.len(),
+                indices: AccessorSparseIndices {
+                    buffer_view: indices_view.0,
+                    byte_offset: 0,
+                    component_ty: indices_component_ty,
+                },
+                values: AccessorSparseValues {
+                    buffer_view: values_view.0,
+                    byte_offset: 0,
+                },
+            }),
+        }))
+    }
+
+    pub fn set_normalized(&mut self, accessor: AccessorIndex, normalized: bool) -> Option<()> {
+        self.accessors.get_mut(accessor)?.normalized = normalized;
+        Some(())
+    }
+
     pub fn create_view_and_accessor<T: BufferType + Copy>(
         &mut self,
         data: &[T],
@@ -183,12 +374,17 @@ impl BufferWriter {
         BufferViewAndAccessorPair::new(view, accessor)
     }
 
-    pub fn write_buffer_views(&self) -> String {
-        serde_json::to_string_pretty(&self.views).unwrap()
+    pub fn write_buffer_views(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(&self.views)?)
+    }
+
+    pub fn write_accessors(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(&self.accessors)?)
     }
 
-    pub fn write_accessors(&self) -> String {
-        serde_json::to_string_pretty(&self.accessors).unwrap()
+    fn align_to(&mut self, alignment: usize) {
+        let len = self.buffer.len().next_multiple_of(alignment);
+        self.buffer.resize(len, 0);
     }
 
     pub fn buffer_len(&self) -> usize {
@@ -202,23 +398,60 @@ impl BufferWriter {
     pub fn data(&self) -> &[u8] {
         &self.buffer
     }
+
+    // Used when importing. Documents may reference several buffers, but the
+    // writer only ever has one, so the buffers are concatenated and the
+    // views are rebased onto the combined buffer.
+    pub(crate) fn set_buffers(&mut self, buffers: Vec<Vec<u8>>) -> Result<()> {
+        let mut buffer = Vec::with_capacity(buffers.iter().map(|x| x.len() + 3).sum());
+        let mut buffer_ranges = Vec::with_capacity(buffers.len());
+        for mut data in buffers {
+            buffer.resize(buffer.len().next_multiple_of(4), 0);
+            buffer_ranges.push(buffer.len()..buffer.len() + data.len());
+            buffer.append(&mut data);
+        }
+
+        for view in self.views.iter_mut() {
+            let buffer_range = buffer_ranges
+                .get(view.buffer)
+                .ok_or(Error::DanglingReference {
+                    from: "buffer view",
+                    to: "buffer",
+                    index: view.buffer,
+                })?;
+            // The offset and length come straight from the document, so
+            // guard against overflow
+            let view_end = view.byte_offset.checked_add(view.byte_len);
+            if view_end.is_none_or(|x| x > buffer_range.len()) {
+                return Err(Error::SpecViolation(format!(
+                    "Buffer view is out of range of buffer {}: {} bytes at offset {}",
+                    view.buffer, view.byte_len, view.byte_offset
+                )));
+            }
+            view.buffer = 0;
+            view.byte_offset += buffer_range.start;
+        }
+
+        self.buffer = buffer;
+        Ok(())
+    }
 }
 
 #[skip_serializing_none]
-#[derive(Debug, Default, Serialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct BufferView {
-    buffer: usize,
-    #[serde(rename = "byteOffset")]
-    byte_offset: usize,
+    pub(crate) buffer: usize,
+    #[serde(rename = "byteOffset", default)]
+    pub(crate) byte_offset: usize,
     #[serde(rename = "byteLength")]
-    byte_len: usize,
+    pub(crate) byte_len: usize,
     #[serde(rename = "byteStride")]
-    stride: Option<usize>,
+    pub(crate) stride: Option<usize>,
     #[serde(rename = "target")]
-    target: Option<BufferViewTarget>,
+    pub(crate) target: Option<BufferViewTarget>,
 }
 
-#[derive(Copy, Clone, Debug, Serialize_repr)]
+#[derive(Copy, Clone, Debug, Serialize_repr, Deserialize_repr)]
 #[repr(usize)]
 pub enum BufferViewTarget {
     ArrayBuffer = 34962,
@@ -226,7 +459,7 @@ pub enum BufferViewTarget {
 }
 
 // https://registry.khronos.org/glTF/specs/2.0/glTF-2.0.html#accessor-data-types
-#[derive(Copy, Clone, Debug, Default, Serialize_repr)]
+#[derive(Copy, Clone, Debug, Default, Serialize_repr, Deserialize_repr)]
 #[repr(usize)]
 pub enum AccessorComponentType {
     SignedByte = 5120,
@@ -254,20 +487,86 @@ impl Default for AccessorDataType {
     }
 }
 
+impl AccessorComponentType {
+    pub fn size(&self) -> usize {
+        match self {
+            Self::SignedByte | Self::UnsignedByte => 1,
+            Self::SignedShort | Self::UnsignedShort => 2,
+            Self::UnsignedInt | Self::Float => 4,
+        }
+    }
+}
+
+impl AccessorDataType {
+    pub fn component_count(&self) -> usize {
+        match self {
+            Self::Scalar => 1,
+            Self::Vec2 => 2,
+            Self::Vec3 => 3,
+            Self::Vec4 | Self::Mat2 => 4,
+            Self::Mat3 => 9,
+            Self::Mat4 => 16,
+        }
+    }
+}
+
+impl Accessor {
+    pub(crate) fn element_size(&self) -> usize {
+        self.component_ty.size() * self.ty.component_count()
+    }
+}
+
 #[skip_serializing_none]
-#[derive(Debug, Default, Serialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct Accessor {
     #[serde(rename = "bufferView")]
-    buffer_view: usize,
+    pub(crate) buffer_view: Option<usize>,
     #[serde(rename = "byteOffset")]
-    byte_offset: usize,
-    count: usize,
+    pub(crate) byte_offset: Option<usize>,
+    pub(crate) count: usize,
     #[serde(rename = "componentType")]
-    component_ty: AccessorComponentType,
+    pub(crate) component_ty: AccessorComponentType,
     #[serde(rename = "type")]
-    ty: AccessorDataType,
+    pub(crate) ty: AccessorDataType,
+    /// Integer components are mapped to [0, 1] (or [-1, 1] if signed) when read.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) normalized: bool,
     #[serde(flatten)]
-    min_max: Option<MinMax<String>>,
+    pub(crate) min_max: Option<MinMax<String>>,
+    pub(crate) sparse: Option<AccessorSparse>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct AccessorSparse {
+    pub(crate) count: usize,
+    pub(crate) indices: AccessorSparseIndices,
+    pub(crate) values: AccessorSparseValues,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct AccessorSparseIndices {
+    #[serde(rename = "bufferView")]
+    pub(crate) buffer_view: usize,
+    #[serde(rename = "byteOffset", default)]
+    pub(crate) byte_offset: usize,
+    #[serde(rename = "componentType")]
+    pub(crate) component_ty: AccessorComponentType,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct AccessorSparseValues {
+    #[serde(rename = "bufferView")]
+    pub(crate) buffer_view: usize,
+    #[serde(rename = "byteOffset", default)]
+    pub(crate) byte_offset: usize,
+}
+
+/// The dense data a sparse accessor is based on. `data` must match what
+/// was written to `view` and is used to compute the accessor's bounds.
+pub struct SparseBase<'a, T> {
+    pub view: BufferViewIndex,
+    pub byte_offset: usize,
+    pub data: &'a [T],
 }
 
 impl BufferType for u16 {
@@ -558,6 +857,51 @@ impl BufferTypeMinMax for [u8; 4] {
     }
 }
 
+impl BufferType for [u16; 4] {
+    const COMPONENT_TY: AccessorComponentType = AccessorComponentType::UnsignedShort;
+    const TY: AccessorDataType = AccessorDataType::Vec4;
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(std::mem::size_of_val(self));
+        for value in self {
+            let mut data = value.to_le_bytes().to_vec();
+            bytes.append(&mut data);
+        }
+        bytes
+    }
+
+    fn stride() -> Option<usize> {
+        Some(std::mem::size_of::<Self>())
+    }
+}
+
+impl BufferTypeMinMax for [u16; 4] {
+    const MIN: Self = [u16::MIN, u16::MIN, u16::MIN, u16::MIN];
+    const MAX: Self = [u16::MAX, u16::MAX, u16::MAX, u16::MAX];
+
+    fn data_max(&self, other: &Self) -> Self {
+        [
+            self[0].data_max(&other[0]),
+            self[1].data_max(&other[1]),
+            self[2].data_max(&other[2]),
+            self[3].data_max(&other[3]),
+        ]
+    }
+
+    fn data_min(&self, other: &Self) -> Self {
+        [
+            self[0].data_min(&other[0]),
+            self[1].data_min(&other[1]),
+            self[2].data_min(&other[2]),
+            self[3].data_min(&other[3]),
+        ]
+    }
+
+    fn write_value(&self) -> String {
+        format!(" [ {}, {}, {}, {} ]", self[0], self[1], self[2], self[3])
+    }
+}
+
 impl BufferType for Mat4 {
     const COMPONENT_TY: AccessorComponentType = AccessorComponentType::Float;
     const TY: AccessorDataType = AccessorDataType::Mat4;
@@ -1,37 +1,34 @@
 use base64::{Engine as _, engine::general_purpose::STANDARD};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use serde_with::skip_serializing_none;
 
 use crate::{
     animation::Animations,
     buffer::BufferWriter,
+    error::{Error, Result},
     material::MaterialData,
     mesh::Mesh,
-    node::{NodeIndex, Nodes},
+    node::Nodes,
+    scene::Scenes,
     skin::Skins,
+    validation::{self, Diagnostic},
 };
 
 // TODO: Move
 #[skip_serializing_none]
-#[derive(Default, Serialize)]
-#[serde(rename_all = "camelCase")]
-struct Buffer {
-    uri: String,
-    byte_length: usize,
-}
-
-// TODO: Move
-#[skip_serializing_none]
-#[derive(Default, Serialize)]
+#[derive(Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
-struct Scene {
-    nodes: Vec<NodeIndex>,
+pub(crate) struct Buffer {
+    pub(crate) uri: Option<String>,
+    pub(crate) byte_length: usize,
 }
 
 // TODO: Move
 pub enum BufferSource<'a> {
     Uri(&'a str),
     Base64,
+    /// The buffer is stored in the BIN chunk of a GLB container.
+    Glb,
 }
 
 #[skip_serializing_none]
@@ -45,21 +42,22 @@ struct Asset {
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct GltfDocument<'a> {
-    scene: usize,
-    scenes: Vec<Scene>,
+    #[serde(flatten)]
+    pub(crate) scenes: &'a Scenes,
     #[serde(skip_serializing_if = "Nodes::is_empty")]
-    nodes: &'a Nodes,
+    pub(crate) nodes: &'a Nodes,
     #[serde(skip_serializing_if = "Vec::<_>::is_empty")]
-    meshes: Vec<Mesh>,
-    buffers: Vec<Buffer>,
+    pub(crate) meshes: Vec<Mesh>,
+    #[serde(skip_serializing_if = "Vec::<_>::is_empty")]
+    pub(crate) buffers: Vec<Buffer>,
     #[serde(flatten)]
-    buffer_writer: &'a BufferWriter,
+    pub(crate) buffer_writer: &'a BufferWriter,
     #[serde(skip_serializing_if = "Skins::is_empty")]
-    skins: &'a Skins,
+    pub(crate) skins: &'a Skins,
     #[serde(skip_serializing_if = "Animations::is_empty")]
-    animations: &'a Animations,
+    pub(crate) animations: &'a Animations,
     #[serde(flatten)]
-    material_data: &'a MaterialData,
+    pub(crate) material_data: &'a MaterialData,
     asset: Asset,
 }
 
@@ -69,32 +67,34 @@ impl<'a> GltfDocument<'a> {
         buffer_writer: &'a BufferWriter,
         meshes: Vec<Mesh>,
         material_data: &'a MaterialData,
-        scene_root: NodeIndex,
+        scenes: &'a Scenes,
         nodes: &'a Nodes,
         skins: &'a Skins,
         animations: &'a Animations,
-    ) -> Self {
-        let buffer_uri = match buffer_source {
-            BufferSource::Uri(uri) => uri.to_owned(),
-            BufferSource::Base64 => {
-                format!(
+    ) -> Result<Self> {
+        // Buffers can't be empty, and a GLB has no BIN chunk to point at
+        let buffers = if buffer_writer.buffer_len() == 0 {
+            Vec::new()
+        } else {
+            let uri = match buffer_source {
+                BufferSource::Uri(uri) => Some(uri.to_owned()),
+                BufferSource::Base64 => Some(format!(
                     "data:application/octet-stream;base64,{}",
                     STANDARD.encode(buffer_writer.data())
-                )
-            }
+                )),
+                BufferSource::Glb => None,
+            };
+            vec![Buffer {
+                uri,
+                byte_length: buffer_writer.buffer_len(),
+            }]
         };
 
-        Self {
-            scene: 0,
-            scenes: vec![Scene {
-                nodes: vec![scene_root],
-            }],
+        Ok(Self {
+            scenes,
             nodes,
             meshes,
-            buffers: vec![Buffer {
-                uri: buffer_uri,
-                byte_length: buffer_writer.buffer_len(),
-            }],
+            buffers,
             buffer_writer,
             skins,
             animations,
@@ -102,6 +102,22 @@ impl<'a> GltfDocument<'a> {
             asset: Asset {
                 version: "2.0".to_owned(),
             },
+        })
+    }
+
+    /// Checks the document for problems that would make it invalid, such
+    /// as references to objects that don't exist or accessors that don't
+    /// fit in their buffer views.
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        validation::validate(self)
+    }
+
+    pub(crate) fn ensure_valid(&self) -> Result<()> {
+        let diagnostics = self.validate();
+        if diagnostics.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Validation(diagnostics))
         }
     }
 }
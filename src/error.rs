@@ -0,0 +1,75 @@
+use crate::validation::Diagnostic;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The document could not be serialized or deserialized.
+    Serialization(serde_json::Error),
+    /// An index into caller provided data (e.g. vertices or indices) is out of range.
+    InvalidIndex {
+        kind: &'static str,
+        index: usize,
+        len: usize,
+    },
+    /// An object refers to another object that doesn't exist.
+    DanglingReference {
+        from: &'static str,
+        to: &'static str,
+        index: usize,
+    },
+    Io(std::io::Error),
+    /// The data can't be represented as a valid glTF document.
+    SpecViolation(String),
+    /// The document failed validation.
+    Validation(Vec<Diagnostic>),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Serialization(error) => write!(f, "Serialization error: {}", error),
+            Error::InvalidIndex { kind, index, len } => {
+                write!(f, "Invalid {} index {} (length is {})", kind, index, len)
+            }
+            Error::DanglingReference { from, to, index } => {
+                write!(
+                    f,
+                    "A {} refers to {} {} which doesn't exist",
+                    from, to, index
+                )
+            }
+            Error::Io(error) => write!(f, "I/O error: {}", error),
+            Error::SpecViolation(message) => write!(f, "Spec violation: {}", message),
+            Error::Validation(diagnostics) => {
+                write!(f, "Validation failed:")?;
+                for diagnostic in diagnostics {
+                    write!(f, "\n  {}", diagnostic)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Serialization(error) => Some(error),
+            Error::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(error: serde_json::Error) -> Self {
+        Error::Serialization(error)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error::Io(error)
+    }
+}
@@ -1,39 +1,72 @@
 use crate::{
     animation::Animations,
     document::{BufferSource, GltfDocument},
-    mesh::Mesh,
+    error::Result,
+    glb::pack_glb,
+    mesh::MeshSource,
 };
 
 use super::{
-    Model, Vertex,
-    buffer::BufferWriter,
-    material::MaterialData,
-    node::{NodeIndex, Nodes},
-    skin::Skins,
+    buffer::BufferWriter, material::MaterialData, node::Nodes, scene::Scenes, skin::Skins,
 };
 
-pub fn write_gltf<T: Vertex>(
+pub fn write_gltf<M: MeshSource>(
     buffer_source: BufferSource,
     buffer_writer: &mut BufferWriter,
-    models: &[Model<T>],
+    models: &[M],
     material_data: &MaterialData,
-    scene_root: NodeIndex,
+    scenes: &Scenes,
     nodes: &Nodes,
     skins: &Skins,
     animations: &Animations,
-) -> String {
-    let meshes: Vec<_> = models.iter().map(|x| Mesh::new(x, buffer_writer)).collect();
+) -> Result<String> {
+    let meshes = models
+        .iter()
+        .map(|x| x.write_mesh(buffer_writer))
+        .collect::<Result<Vec<_>>>()?;
 
     let document = GltfDocument::new(
         buffer_source,
         &buffer_writer,
         meshes,
         material_data,
-        scene_root,
+        scenes,
         nodes,
         skins,
         animations,
-    );
+    )?;
+    document.ensure_valid()?;
 
-    serde_json::to_string_pretty(&document).unwrap()
+    Ok(serde_json::to_string_pretty(&document)?)
+}
+
+pub fn write_glb<M: MeshSource>(
+    buffer_writer: &mut BufferWriter,
+    models: &[M],
+    material_data: &MaterialData,
+    scenes: &Scenes,
+    nodes: &Nodes,
+    skins: &Skins,
+    animations: &Animations,
+) -> Result<Vec<u8>> {
+    let meshes = models
+        .iter()
+        .map(|x| x.write_mesh(buffer_writer))
+        .collect::<Result<Vec<_>>>()?;
+
+    let document = GltfDocument::new(
+        BufferSource::Glb,
+        buffer_writer,
+        meshes,
+        material_data,
+        scenes,
+        nodes,
+        skins,
+        animations,
+    )?;
+    document.ensure_valid()?;
+
+    // The JSON chunk doesn't need to be human readable
+    let json = serde_json::to_string(&document)?;
+    Ok(pack_glb(&json, buffer_writer.data()))
 }
@@ -0,0 +1,101 @@
+use crate::error::{Error, Result};
+
+// https://registry.khronos.org/glTF/specs/2.0/glTF-2.0.html#binary-gltf-layout
+const GLB_MAGIC: u32 = 0x46546C67;
+const GLB_VERSION: u32 = 2;
+const CHUNK_TYPE_JSON: u32 = 0x4E4F534A;
+const CHUNK_TYPE_BIN: u32 = 0x004E4942;
+const HEADER_LEN: usize = 12;
+const CHUNK_HEADER_LEN: usize = 8;
+
+fn padded_len(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+fn write_chunk(output: &mut Vec<u8>, chunk_type: u32, data: &[u8], padding: u8) {
+    let chunk_len = padded_len(data.len());
+    output.extend_from_slice(&(chunk_len as u32).to_le_bytes());
+    output.extend_from_slice(&chunk_type.to_le_bytes());
+    output.extend_from_slice(data);
+    output.resize(output.len() + (chunk_len - data.len()), padding);
+}
+
+/// Packs a JSON document and its binary buffer into a GLB container.
+///
+/// The JSON chunk is padded with spaces and the BIN chunk with zeros so
+/// that both start and end on 4-byte boundaries. The BIN chunk is
+/// omitted when `bin` is empty.
+pub fn pack_glb(json: &str, bin: &[u8]) -> Vec<u8> {
+    let json_len = padded_len(json.len());
+    let bin_len = if bin.is_empty() {
+        0
+    } else {
+        CHUNK_HEADER_LEN + padded_len(bin.len())
+    };
+    let total_len = HEADER_LEN + CHUNK_HEADER_LEN + json_len + bin_len;
+
+    let mut output = Vec::with_capacity(total_len);
+    output.extend_from_slice(&GLB_MAGIC.to_le_bytes());
+    output.extend_from_slice(&GLB_VERSION.to_le_bytes());
+    output.extend_from_slice(&(total_len as u32).to_le_bytes());
+    write_chunk(&mut output, CHUNK_TYPE_JSON, json.as_bytes(), b' ');
+    if !bin.is_empty() {
+        write_chunk(&mut output, CHUNK_TYPE_BIN, bin, 0);
+    }
+    debug_assert_eq!(output.len(), total_len);
+    output
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+pub fn is_glb(data: &[u8]) -> bool {
+    read_u32(data, 0) == Some(GLB_MAGIC)
+}
+
+/// Splits a GLB container into its JSON chunk and optional BIN chunk.
+pub fn unpack_glb(data: &[u8]) -> Result<(&str, Option<&[u8]>)> {
+    let invalid_data = |message: &str| Error::SpecViolation(message.to_owned());
+
+    if !is_glb(data) {
+        return Err(invalid_data("Missing GLB magic"));
+    }
+    let version = read_u32(data, 4).ok_or_else(|| invalid_data("Truncated GLB header"))?;
+    if version != GLB_VERSION {
+        return Err(invalid_data("Unsupported GLB version"));
+    }
+    let total_len = read_u32(data, 8).ok_or_else(|| invalid_data("Truncated GLB header"))?;
+    let data = data
+        .get(..total_len as usize)
+        .ok_or_else(|| invalid_data("GLB length exceeds the data length"))?;
+
+    let mut json = None;
+    let mut bin = None;
+    let mut offset = HEADER_LEN;
+    while offset < data.len() {
+        let chunk_len = read_u32(data, offset).ok_or_else(|| invalid_data("Truncated chunk"))?;
+        let chunk_type =
+            read_u32(data, offset + 4).ok_or_else(|| invalid_data("Truncated chunk"))?;
+        let chunk_start = offset + CHUNK_HEADER_LEN;
+        let chunk_end = chunk_start + chunk_len as usize;
+        let chunk = data
+            .get(chunk_start..chunk_end)
+            .ok_or_else(|| invalid_data("Truncated chunk"))?;
+        match chunk_type {
+            CHUNK_TYPE_JSON if json.is_none() => {
+                let text = std::str::from_utf8(chunk)
+                    .map_err(|_| invalid_data("JSON chunk is not valid UTF-8"))?;
+                json = Some(text);
+            }
+            CHUNK_TYPE_BIN if json.is_some() && bin.is_none() => bin = Some(chunk),
+            // Unknown chunks must be ignored
+            _ => {}
+        }
+        offset = chunk_end;
+    }
+
+    let json = json.ok_or_else(|| invalid_data("Missing JSON chunk"))?;
+    Ok((json, bin))
+}
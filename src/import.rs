@@ -0,0 +1,337 @@
+use std::path::Path;
+
+use base64::{Engine as _, engine::general_purpose::STANDARD};
+use serde::Deserialize;
+
+use crate::{
+    animation::Animations,
+    buffer::BufferWriter,
+    document::{Buffer, BufferSource, GltfDocument},
+    error::{Error, Result},
+    glb::{is_glb, pack_glb, unpack_glb},
+    material::MaterialData,
+    mesh::Mesh,
+    node::Nodes,
+    scene::Scenes,
+    skin::Skins,
+};
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawDocument {
+    #[serde(flatten)]
+    scenes: Scenes,
+    #[serde(default)]
+    nodes: Nodes,
+    #[serde(default)]
+    meshes: Vec<Mesh>,
+    #[serde(default)]
+    buffers: Vec<Buffer>,
+    #[serde(flatten)]
+    buffer_writer: BufferWriter,
+    #[serde(default)]
+    skins: Skins,
+    #[serde(default)]
+    animations: Animations,
+    #[serde(flatten)]
+    material_data: MaterialData,
+    #[serde(default)]
+    extensions_required: Vec<String>,
+}
+
+pub struct ImportedDocument {
+    pub buffer_writer: BufferWriter,
+    pub meshes: Vec<Mesh>,
+    pub material_data: MaterialData,
+    pub scenes: Scenes,
+    pub nodes: Nodes,
+    pub skins: Skins,
+    pub animations: Animations,
+}
+
+impl ImportedDocument {
+    pub fn write_gltf(&self, buffer_source: BufferSource) -> Result<String> {
+        let document = self.to_document(buffer_source)?;
+        Ok(serde_json::to_string_pretty(&document)?)
+    }
+
+    pub fn write_glb(&self) -> Result<Vec<u8>> {
+        let document = self.to_document(BufferSource::Glb)?;
+        let json = serde_json::to_string(&document)?;
+        Ok(pack_glb(&json, self.buffer_writer.data()))
+    }
+
+    fn to_document<'a>(&'a self, buffer_source: BufferSource<'a>) -> Result<GltfDocument<'a>> {
+        let document = GltfDocument::new(
+            buffer_source,
+            &self.buffer_writer,
+            self.meshes.clone(),
+            &self.material_data,
+            &self.scenes,
+            &self.nodes,
+            &self.skins,
+            &self.animations,
+        )?;
+        document.ensure_valid()?;
+        Ok(document)
+    }
+}
+
+fn read_buffer(
+    index: usize,
+    buffer: &Buffer,
+    glb_bin: Option<&[u8]>,
+    base_path: Option<&Path>,
+) -> Result<Vec<u8>> {
+    let mut data = match &buffer.uri {
+        None => {
+            // Only the first buffer may refer to the GLB BIN chunk
+            match glb_bin {
+                Some(bin) if index == 0 => bin.to_vec(),
+                _ => {
+                    return Err(Error::SpecViolation(format!("Buffer {} has no uri", index)));
+                }
+            }
+        }
+        Some(uri) if uri.starts_with("data:") => {
+            let (_, encoded) = uri.split_once(";base64,").ok_or_else(|| {
+                Error::SpecViolation(format!("Buffer {} is not a base64 data uri", index))
+            })?;
+            STANDARD.decode(encoded).map_err(|error| {
+                Error::SpecViolation(format!("Buffer {} has invalid base64: {}", index, error))
+            })?
+        }
+        Some(uri) => {
+            let path = match base_path {
+                Some(base_path) => base_path.join(uri),
+                None => uri.into(),
+            };
+            std::fs::read(path)?
+        }
+    };
+
+    // GLB BIN chunks are padded, so they can be longer than the buffer
+    if data.len() < buffer.byte_length {
+        return Err(Error::SpecViolation(format!(
+            "Buffer {} is {} bytes but byteLength is {}",
+            index,
+            data.len(),
+            buffer.byte_length
+        )));
+    }
+    data.truncate(buffer.byte_length);
+    Ok(data)
+}
+
+/// Reads a glTF (JSON) or GLB document. External buffers are resolved
+/// relative to `base_path`, or the working directory if it is `None`.
+///
+/// Only the parts of the document this crate can write back are kept.
+/// Cameras, `node.camera`, `extensionsUsed` and every `extensions` and
+/// `extras` object (other than mesh target names) are discarded. Documents
+/// with `extensionsRequired` are rejected, since dropping those extensions
+/// would lose data.
+pub fn read_gltf(data: &[u8], base_path: Option<&Path>) -> Result<ImportedDocument> {
+    let (json, glb_bin) = if is_glb(data) {
+        unpack_glb(data)?
+    } else {
+        let json = std::str::from_utf8(data)
+            .map_err(|_| Error::SpecViolation("Document is not valid UTF-8".to_owned()))?;
+        (json, None)
+    };
+
+    let mut document: RawDocument = serde_json::from_str(json)?;
+    if !document.extensions_required.is_empty() {
+        return Err(Error::SpecViolation(format!(
+            "Document requires unsupported extensions: {}",
+            document.extensions_required.join(", ")
+        )));
+    }
+
+    let buffers = document
+        .buffers
+        .iter()
+        .enumerate()
+        .map(|(i, buffer)| read_buffer(i, buffer, glb_bin, base_path))
+        .collect::<Result<Vec<_>>>()?;
+    document.buffer_writer.set_buffers(buffers)?;
+
+    Ok(ImportedDocument {
+        buffer_writer: document.buffer_writer,
+        meshes: document.meshes,
+        material_data: document.material_data,
+        scenes: document.scenes,
+        nodes: document.nodes,
+        skins: document.skins,
+        animations: document.animations,
+    })
+}
+
+pub fn read_gltf_file<P: AsRef<Path>>(path: P) -> Result<ImportedDocument> {
+    let path = path.as_ref();
+    let data = std::fs::read(path)?;
+    read_gltf(&data, path.parent())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Mesh as ModelMesh, Model,
+        export::write_glb,
+        material::{Image, Material, PbrMetallicRoughness, Texture, TextureInfo},
+        mesh::PrimitiveMode,
+        node::{MeshIndex, Node},
+        scene::Scene,
+        vertex::VertexBuffer,
+    };
+
+    fn image_data<'a>(document: &'a ImportedDocument, image: &Image) -> &'a [u8] {
+        let view = document
+            .buffer_writer
+            .views
+            .get(image.buffer_view.unwrap())
+            .unwrap();
+        &document.buffer_writer.data()[view.byte_offset..view.byte_offset + view.byte_len]
+    }
+
+    #[test]
+    fn round_trip_glb_with_embedded_image() {
+        let png = b"\x89PNG\r\n\x1a\nnot really a png";
+        let mut buffer_writer = BufferWriter::new();
+
+        let mut vertices = VertexBuffer::new(3);
+        vertices
+            .add_attribute(
+                "POSITION",
+                vec![[0.0f32, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]],
+            )
+            .unwrap();
+        let model = Model {
+            indices: Vec::new(),
+            vertices,
+            meshes: vec![ModelMesh {
+                texture_index: None,
+                indices_range: None,
+                vertex_range: None,
+                mode: PrimitiveMode::Triangles,
+            }],
+            morph_targets: Vec::new(),
+        };
+
+        let mut material_data = MaterialData::new();
+        let image =
+            material_data.add_images(Image::from_data(&mut buffer_writer, png, "image/png"));
+        let texture = material_data.add_texture(Texture {
+            sampler: None,
+            source: image,
+        });
+        material_data.add_material(Material {
+            pbr_metallic_roughness: PbrMetallicRoughness {
+                base_color_texture: Some(TextureInfo::new(texture)),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+
+        let mut nodes = Nodes::new(1);
+        let node = nodes.add_node(Node {
+            mesh: Some(MeshIndex(0)),
+            ..Default::default()
+        });
+        let mut scenes = Scenes::new();
+        let scene = scenes.add_scene(Scene {
+            name: None,
+            nodes: vec![node],
+        });
+        scenes.set_default_scene(Some(scene));
+
+        let glb = write_glb(
+            &mut buffer_writer,
+            &[model],
+            &material_data,
+            &scenes,
+            &nodes,
+            &Skins::new(),
+            &Animations::new(0),
+        )
+        .unwrap();
+
+        let document = read_gltf(&glb, None).unwrap();
+        let document = read_gltf(&document.write_glb().unwrap(), None).unwrap();
+        assert!(document.meshes[0].primitives[0].material.is_none());
+        let image = document.material_data.images.iter().next().unwrap();
+        assert!(image.uri.is_none());
+        assert_eq!(image.mime_type.as_deref(), Some("image/png"));
+        assert_eq!(image_data(&document, image), png);
+        let texture = document.material_data.textures.iter().next().unwrap();
+        assert!(texture.sampler.is_none());
+    }
+
+    #[test]
+    fn read_optional_references() {
+        let json = r#"{
+            "asset": { "version": "2.0" },
+            "nodes": [{}],
+            "skins": [{ "joints": [0] }],
+            "images": [{ "uri": "a.png" }],
+            "textures": [{ "source": 0 }]
+        }"#;
+        let document = read_gltf(json.as_bytes(), None).unwrap();
+        assert!(
+            document
+                .skins
+                .skins
+                .iter()
+                .next()
+                .unwrap()
+                .inverse_bind_matrices
+                .is_none()
+        );
+        document.write_gltf(BufferSource::Base64).unwrap();
+    }
+
+    #[test]
+    fn read_default_pbr_factors() {
+        let json = r#"{
+            "asset": { "version": "2.0" },
+            "materials": [{ "name": "m" }]
+        }"#;
+        let document = read_gltf(json.as_bytes(), None).unwrap();
+        let material = document.material_data.materials.iter().next().unwrap();
+        assert_eq!(material.pbr_metallic_roughness.metallic_factor, 1.0);
+        assert_eq!(material.pbr_metallic_roughness.roughness_factor, 1.0);
+
+        let json = document.write_gltf(BufferSource::Base64).unwrap();
+        let document = read_gltf(json.as_bytes(), None).unwrap();
+        let material = document.material_data.materials.iter().next().unwrap();
+        assert_eq!(material.pbr_metallic_roughness.metallic_factor, 1.0);
+        assert_eq!(material.pbr_metallic_roughness.roughness_factor, 1.0);
+    }
+
+    #[test]
+    fn reject_required_extensions() {
+        let json = r#"{
+            "asset": { "version": "2.0" },
+            "extensionsUsed": ["KHR_draco_mesh_compression"],
+            "extensionsRequired": ["KHR_draco_mesh_compression"]
+        }"#;
+        assert!(matches!(
+            read_gltf(json.as_bytes(), None),
+            Err(Error::SpecViolation(_))
+        ));
+    }
+
+    #[test]
+    fn reject_overflowing_buffer_view() {
+        let json = r#"{
+            "asset": { "version": "2.0" },
+            "buffers": [{ "uri": "data:application/octet-stream;base64,AAAAAA==", "byteLength": 4 }],
+            "bufferViews": [{ "buffer": 0, "byteOffset": 18446744073709551615, "byteLength": 2 }]
+        }"#;
+        assert!(matches!(
+            read_gltf(json.as_bytes(), None),
+            Err(Error::SpecViolation(_))
+        ));
+    }
+}
@@ -1,31 +1,175 @@
-use self::buffer::BufferWriter;
-use std::ops::Range;
+use self::{
+    buffer::BufferWriter,
+    error::{Error, Result},
+    mesh::PrimitiveMode,
+    transform::CoordinateSystem,
+    vertex::VertexSource,
+};
+use std::{collections::HashMap, ops::Range};
 
 pub mod animation;
 pub mod buffer;
 pub mod document;
+pub mod error;
 pub mod export;
+pub mod glb;
+pub mod import;
 pub mod material;
 pub mod mesh;
 pub mod node;
+pub mod scene;
 pub mod skin;
 pub mod storage;
 pub mod transform;
+pub mod validation;
+pub mod vertex;
 
 pub trait Vertex: Sized {
     fn write_slices(writer: &mut BufferWriter, vertices: &[Self]) -> Vec<(&'static str, usize)>;
+    /// Converts the vertices from `system` to glTF's coordinate system.
+    /// `vertex_def!` types convert their POSITION, NORMAL and TANGENT
+    /// fields. The default leaves the vertices unchanged.
+    fn convert_coordinates(_vertices: &mut [Self], _system: &CoordinateSystem) {}
 }
 
 #[derive(Clone)]
 pub struct Mesh {
-    pub texture_index: usize,
-    pub indices_range: Range<usize>,
+    /// The material the primitive uses. Primitives without one use the
+    /// default material.
+    pub texture_index: Option<usize>,
+    /// Draws the vertices in order when `None`.
+    pub indices_range: Option<Range<usize>>,
+    /// The vertices the primitive uses. All of them are used when `None`.
+    /// Indices still refer to the whole vertex array.
+    pub vertex_range: Option<Range<usize>>,
+    pub mode: PrimitiveMode,
 }
 
+/// Per-vertex deltas that are blended onto the base vertices by the mesh
+/// weights. Empty attributes are left out of the target.
+#[derive(Clone, Debug, Default)]
+pub struct MorphTarget {
+    pub name: String,
+    pub default_weight: f32,
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub tangents: Vec<[f32; 3]>,
+}
+
+impl MorphTarget {
+    pub(crate) fn check_len(&self, vertex_count: usize) -> Result<()> {
+        for attribute in [&self.positions, &self.normals, &self.tangents] {
+            if !attribute.is_empty() && attribute.len() != vertex_count {
+                return Err(Error::SpecViolation(format!(
+                    "Morph target \"{}\" has {} elements but there are {} vertices",
+                    self.name,
+                    attribute.len(),
+                    vertex_count
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `V` is usually a `Vec` of a `vertex_def!` type, or a `vertex::VertexBuffer`
+/// for layouts that are only known at runtime.
 pub struct Model<V> {
     pub indices: Vec<u32>,
-    pub vertices: Vec<V>,
+    pub vertices: V,
     pub meshes: Vec<Mesh>,
+    pub morph_targets: Vec<MorphTarget>,
+}
+
+impl<V: VertexSource> Model<V> {
+    /// Splits the shared vertex array so that each mesh only has the
+    /// vertices it uses, in the order it first uses them. The indices are
+    /// remapped to match and every mesh is given its own vertex range.
+    pub fn compact(&self) -> Result<Self> {
+        let vertex_count = self.vertices.vertex_count();
+        for target in &self.morph_targets {
+            target.check_len(vertex_count)?;
+        }
+        let mut gathered = Vec::new();
+        let mut indices = Vec::with_capacity(self.indices.len());
+        let mut meshes = Vec::with_capacity(self.meshes.len());
+        for mesh in &self.meshes {
+            let vertex_start = gathered.len();
+            let indices_range = match &mesh.indices_range {
+                Some(indices_range) => {
+                    let mesh_indices =
+                        self.indices
+                            .get(indices_range.clone())
+                            .ok_or(Error::InvalidIndex {
+                                kind: "index",
+                                index: indices_range.end,
+                                len: self.indices.len(),
+                            })?;
+                    let index_start = indices.len();
+                    let mut remapped = HashMap::new();
+                    for index in mesh_indices {
+                        let index = *index as usize;
+                        if index >= vertex_count {
+                            return Err(Error::InvalidIndex {
+                                kind: "vertex",
+                                index,
+                                len: vertex_count,
+                            });
+                        }
+                        let new_index = *remapped.entry(index).or_insert_with(|| {
+                            gathered.push(index);
+                            gathered.len() - 1
+                        });
+                        indices.push(new_index as u32);
+                    }
+                    Some(index_start..indices.len())
+                }
+                None => {
+                    let vertex_range = mesh.vertex_range.clone().unwrap_or(0..vertex_count);
+                    if vertex_range.end > vertex_count {
+                        return Err(Error::InvalidIndex {
+                            kind: "vertex",
+                            index: vertex_range.end,
+                            len: vertex_count,
+                        });
+                    }
+                    gathered.extend(vertex_range);
+                    None
+                }
+            };
+            meshes.push(Mesh {
+                indices_range,
+                vertex_range: Some(vertex_start..gathered.len()),
+                ..mesh.clone()
+            });
+        }
+
+        let gather = |data: &Vec<[f32; 3]>| -> Vec<[f32; 3]> {
+            if data.is_empty() {
+                Vec::new()
+            } else {
+                gathered.iter().map(|x| data[*x]).collect()
+            }
+        };
+        let morph_targets = self
+            .morph_targets
+            .iter()
+            .map(|x| MorphTarget {
+                name: x.name.clone(),
+                default_weight: x.default_weight,
+                positions: gather(&x.positions),
+                normals: gather(&x.normals),
+                tangents: gather(&x.tangents),
+            })
+            .collect();
+
+        Ok(Self {
+            indices,
+            vertices: self.vertices.gather(&gathered),
+            meshes,
+            morph_targets,
+        })
+    }
 }
 
 pub fn add_and_get_index<T>(vec: &mut Vec<T>, value: T) -> usize {
@@ -37,7 +181,7 @@ pub fn add_and_get_index<T>(vec: &mut Vec<T>, value: T) -> usize {
 #[macro_export]
 macro_rules! enum_with_str {
     ($name:ident { $($var_name:ident : $str_value:literal),* $(,)* }) => {
-        #[derive(Copy, Clone, Debug, serde::Serialize)]
+        #[derive(Copy, Clone, Debug, serde::Serialize, serde::Deserialize)]
         pub enum $name {
             $(
                 #[serde(rename = $str_value)]
@@ -49,6 +193,96 @@ macro_rules! enum_with_str {
 
 #[macro_export]
 macro_rules! vertex_def {
+    // Shared by both layouts
+    (@convert_coordinates $(($attribute_name:literal) $field_name:ident),*) => {
+        fn convert_coordinates(
+            vertices: &mut [Self],
+            system: &gltf::transform::CoordinateSystem
+        ) {
+            use gltf::transform::ConvertAttribute;
+
+            for vertex in vertices {
+                $(
+                    vertex.$field_name.convert_attribute($attribute_name, system);
+                )*
+            }
+        }
+    };
+    // Writes the vertices once as a single strided buffer view with one
+    // accessor per field, instead of one buffer view per field.
+    (interleaved $name:ident { $(($attribute_name:literal) $field_name:ident : $field_ty:ty),* $(,)* }) => {
+        #[repr(C)]
+        #[derive(Copy, Clone, Debug, Default)]
+        pub struct $name {
+            $(
+                pub $field_name : $field_ty,
+            )*
+        }
+
+        // Attributes in a strided view must start on a multiple of 4 bytes
+        // and the stride can't be more than 252 bytes
+        const _: () = {
+            $(
+                assert!(
+                    std::mem::offset_of!($name, $field_name).is_multiple_of(4),
+                    concat!(
+                        stringify!($name), "::", stringify!($field_name),
+                        " must be at an offset that is a multiple of 4"
+                    )
+                );
+            )*
+            assert!(
+                std::mem::size_of::<$name>().next_multiple_of(4) <= 252,
+                concat!(stringify!($name), " is larger than the maximum stride of 252 bytes")
+            );
+        };
+
+        impl gltf::Vertex for $name {
+            fn write_slices(
+                writer: &mut gltf::buffer::BufferWriter,
+                vertices: &[Self]
+            ) -> Vec<(&'static str, usize)> {
+                use gltf::buffer::{BufferType, BufferTypeMinMax};
+
+                // Vertex attribute strides must be a multiple of 4
+                let stride = std::mem::size_of::<Self>().next_multiple_of(4);
+                let mut data = vec![0u8; stride * vertices.len()];
+                for (vertex, vertex_data) in vertices.iter().zip(data.chunks_exact_mut(stride)) {
+                    $(
+                        let offset = std::mem::offset_of!(Self, $field_name);
+                        let bytes = vertex.$field_name.to_bytes();
+                        vertex_data[offset..offset + bytes.len()].copy_from_slice(&bytes);
+                    )*
+                }
+                let view = writer.create_strided_view(&data, stride, Some(gltf::buffer::BufferViewTarget::ArrayBuffer));
+
+                $(
+                    let $field_name = {
+                        let mut max = <$field_ty as BufferTypeMinMax>::MIN;
+                        let mut min = <$field_ty as BufferTypeMinMax>::MAX;
+                        for vertex in vertices {
+                            max = vertex.$field_name.data_max(&max);
+                            min = vertex.$field_name.data_min(&min);
+                        }
+                        writer.create_accessor_with_min_max(
+                            view,
+                            std::mem::offset_of!(Self, $field_name),
+                            vertices.len(),
+                            gltf::buffer::MinMax { min, max },
+                        )
+                    };
+                )*
+
+                vec![
+                    $(
+                        ($attribute_name, $field_name.0),
+                    )*
+                ]
+            }
+
+            gltf::vertex_def!(@convert_coordinates $(($attribute_name) $field_name),*);
+        }
+    };
     ($name:ident { $(($attribute_name:literal) $field_name:ident : $field_ty:ty),* $(,)* }) => {
         #[repr(C)]
         #[derive(Copy, Clone, Debug, Default)]
@@ -85,6 +319,8 @@ macro_rules! vertex_def {
 
                 attributes
             }
+
+            gltf::vertex_def!(@convert_coordinates $(($attribute_name) $field_name),*);
         }
     };
 }
@@ -1,55 +1,210 @@
-use glam::Vec4;
-use serde::Serialize;
-use serde_repr::Serialize_repr;
+use glam::{Vec3, Vec4};
+use serde::{Deserialize, Serialize};
+use serde_repr::{Deserialize_repr, Serialize_repr};
 use serde_with::skip_serializing_none;
 
-use crate::storage::{Storage, StorageIndex};
+use crate::{
+    buffer::{BufferViewIndex, BufferWriter},
+    enum_with_str,
+    storage::{Storage, StorageIndex},
+};
 
 pub type MaterialIndex = StorageIndex<Material>;
 pub type TextureIndex = StorageIndex<Texture>;
 pub type ImageIndex = StorageIndex<Image>;
 pub type SamplerIndex = StorageIndex<Sampler>;
 
-#[derive(Clone, Debug, Default, Serialize)]
+enum_with_str!(AlphaMode {
+    Opaque: "OPAQUE",
+    Mask: "MASK",
+    Blend: "BLEND",
+});
+
+#[skip_serializing_none]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Material {
+    pub name: Option<String>,
+    #[serde(default = "default_pbr_metallic_roughness")]
     pub pbr_metallic_roughness: PbrMetallicRoughness,
+    pub normal_texture: Option<NormalTextureInfo>,
+    pub occlusion_texture: Option<OcclusionTextureInfo>,
+    pub emissive_texture: Option<TextureInfo>,
+    pub emissive_factor: Option<Vec3>,
+    /// Treated as `OPAQUE` when omitted.
+    pub alpha_mode: Option<AlphaMode>,
+    /// Only allowed when `alpha_mode` is `MASK`. Treated as 0.5 when omitted.
+    pub alpha_cutoff: Option<f32>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub double_sided: bool,
+}
+
+impl Material {
+    /// Every texture the material samples, as (JSON path, texture, texcoord set).
+    pub(crate) fn texture_infos(&self) -> Vec<(&'static str, TextureIndex, usize)> {
+        let pbr = &self.pbr_metallic_roughness;
+        let mut infos = Vec::new();
+        if let Some(info) = &pbr.base_color_texture {
+            infos.push((
+                "pbrMetallicRoughness/baseColorTexture",
+                info.index,
+                info.tex_coord,
+            ));
+        }
+        if let Some(info) = &pbr.metallic_roughness_texture {
+            infos.push((
+                "pbrMetallicRoughness/metallicRoughnessTexture",
+                info.index,
+                info.tex_coord,
+            ));
+        }
+        if let Some(info) = &self.normal_texture {
+            infos.push(("normalTexture", info.index, info.tex_coord));
+        }
+        if let Some(info) = &self.occlusion_texture {
+            infos.push(("occlusionTexture", info.index, info.tex_coord));
+        }
+        if let Some(info) = &self.emissive_texture {
+            infos.push(("emissiveTexture", info.index, info.tex_coord));
+        }
+        infos
+    }
 }
 
 #[skip_serializing_none]
-#[derive(Clone, Debug, Default, Serialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct PbrMetallicRoughness {
     pub base_color_texture: Option<BaseColorTexture>,
     pub base_color_factor: Option<Vec4>,
+    #[serde(default = "default_factor")]
     pub metallic_factor: f32,
+    #[serde(default = "default_factor")]
     pub roughness_factor: f32,
+    /// Metalness is read from the blue channel and roughness from the green channel.
+    pub metallic_roughness_texture: Option<TextureInfo>,
+}
+
+// The spec defaults factors, scales and strengths to 1.0 when they are omitted
+fn default_factor() -> f32 {
+    1.0
+}
+
+fn default_pbr_metallic_roughness() -> PbrMetallicRoughness {
+    PbrMetallicRoughness {
+        metallic_factor: default_factor(),
+        roughness_factor: default_factor(),
+        ..Default::default()
+    }
+}
+
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
 }
 
-#[derive(Clone, Debug, Default, Serialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
-pub struct BaseColorTexture {
+pub struct TextureInfo {
     pub index: TextureIndex,
+    /// The `TEXCOORD_<n>` attribute used to sample the texture.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub tex_coord: usize,
 }
 
-impl BaseColorTexture {
+impl TextureInfo {
     pub fn new(index: TextureIndex) -> Self {
-        Self { index }
+        Self {
+            index,
+            tex_coord: 0,
+        }
     }
 }
 
-#[derive(Clone, Default, Serialize)]
+pub type BaseColorTexture = TextureInfo;
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NormalTextureInfo {
+    pub index: TextureIndex,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub tex_coord: usize,
+    #[serde(default = "default_factor")]
+    pub scale: f32,
+}
+
+impl NormalTextureInfo {
+    pub fn new(index: TextureIndex) -> Self {
+        Self {
+            index,
+            tex_coord: 0,
+            scale: 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OcclusionTextureInfo {
+    pub index: TextureIndex,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub tex_coord: usize,
+    #[serde(default = "default_factor")]
+    pub strength: f32,
+}
+
+impl OcclusionTextureInfo {
+    pub fn new(index: TextureIndex) -> Self {
+        Self {
+            index,
+            tex_coord: 0,
+            strength: 1.0,
+        }
+    }
+}
+
+#[skip_serializing_none]
+#[derive(Clone, Default, Serialize, Deserialize)]
 pub struct Texture {
-    pub sampler: SamplerIndex,
+    /// Uses repeat wrapping and auto filtering when `None`.
+    pub sampler: Option<SamplerIndex>,
     pub source: ImageIndex,
 }
 
-#[derive(Clone, Default, Serialize)]
+/// An image is either referenced by `uri` or stored in a buffer view,
+/// in which case `mime_type` is required.
+#[skip_serializing_none]
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Image {
-    pub uri: String,
+    pub uri: Option<String>,
+    pub buffer_view: Option<BufferViewIndex>,
+    pub mime_type: Option<String>,
 }
 
-#[derive(Copy, Clone, Debug, Default, Serialize_repr)]
+impl Image {
+    pub fn from_uri(uri: impl Into<String>) -> Self {
+        Self {
+            uri: Some(uri.into()),
+            ..Default::default()
+        }
+    }
+
+    /// Stores the encoded image (e.g. a PNG file) in the buffer, which is
+    /// how GLB files usually embed their images.
+    pub fn from_data(
+        buffer_writer: &mut BufferWriter,
+        data: &[u8],
+        mime_type: impl Into<String>,
+    ) -> Self {
+        Self {
+            uri: None,
+            buffer_view: Some(buffer_writer.create_view(data, None)),
+            mime_type: Some(mime_type.into()),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, Serialize_repr, Deserialize_repr)]
 #[repr(usize)]
 pub enum MagFilter {
     #[default]
@@ -57,7 +212,7 @@ pub enum MagFilter {
     Linear = 9729,
 }
 
-#[derive(Copy, Clone, Debug, Default, Serialize_repr)]
+#[derive(Copy, Clone, Debug, Default, Serialize_repr, Deserialize_repr)]
 #[repr(usize)]
 pub enum MinFilter {
     #[default]
@@ -69,7 +224,7 @@ pub enum MinFilter {
     LinearMipMapLinear = 9987,
 }
 
-#[derive(Copy, Clone, Debug, Default, Serialize_repr)]
+#[derive(Copy, Clone, Debug, Default, Serialize_repr, Deserialize_repr)]
 #[repr(usize)]
 pub enum Wrap {
     #[default]
@@ -78,25 +233,34 @@ pub enum Wrap {
     Repeat = 10497,
 }
 
-#[derive(Clone, Default, Serialize)]
+#[derive(Clone, Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Sampler {
+    #[serde(default)]
     pub mag_filter: MagFilter,
+    #[serde(default)]
     pub min_filter: MinFilter,
+    #[serde(default = "default_wrap")]
     pub wrap_s: Wrap,
+    #[serde(default = "default_wrap")]
     pub wrap_t: Wrap,
 }
 
-#[derive(Clone, Default, Serialize)]
+// The spec defaults to repeat when the wrap mode is omitted
+fn default_wrap() -> Wrap {
+    Wrap::Repeat
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
 pub struct MaterialData {
-    #[serde(skip_serializing_if = "Storage::is_empty")]
-    materials: Storage<Material>,
-    #[serde(skip_serializing_if = "Storage::is_empty")]
-    textures: Storage<Texture>,
-    #[serde(skip_serializing_if = "Storage::is_empty")]
-    images: Storage<Image>,
-    #[serde(skip_serializing_if = "Storage::is_empty")]
-    samplers: Storage<Sampler>,
+    #[serde(default, skip_serializing_if = "Storage::is_empty")]
+    pub(crate) materials: Storage<Material>,
+    #[serde(default, skip_serializing_if = "Storage::is_empty")]
+    pub(crate) textures: Storage<Texture>,
+    #[serde(default, skip_serializing_if = "Storage::is_empty")]
+    pub(crate) images: Storage<Image>,
+    #[serde(default, skip_serializing_if = "Storage::is_empty")]
+    pub(crate) samplers: Storage<Sampler>,
 }
 
 impl MaterialData {
@@ -124,4 +288,68 @@ impl MaterialData {
     pub fn add_sampler(&mut self, sampler: Sampler) -> SamplerIndex {
         self.samplers.allocate_with(sampler)
     }
+
+    pub fn allocate_material(&mut self) -> MaterialIndex {
+        self.materials.allocate()
+    }
+
+    pub fn update_material(&mut self, index: MaterialIndex, material: Material) -> Option<()> {
+        self.materials.update(index, material)
+    }
+
+    pub fn get_material(&self, index: MaterialIndex) -> Option<&Material> {
+        self.materials.get(index)
+    }
+
+    pub fn get_material_mut(&mut self, index: MaterialIndex) -> Option<&mut Material> {
+        self.materials.get_mut(index)
+    }
+
+    pub fn allocate_texture(&mut self) -> TextureIndex {
+        self.textures.allocate()
+    }
+
+    pub fn update_texture(&mut self, index: TextureIndex, texture: Texture) -> Option<()> {
+        self.textures.update(index, texture)
+    }
+
+    pub fn get_texture(&self, index: TextureIndex) -> Option<&Texture> {
+        self.textures.get(index)
+    }
+
+    pub fn get_texture_mut(&mut self, index: TextureIndex) -> Option<&mut Texture> {
+        self.textures.get_mut(index)
+    }
+
+    pub fn allocate_image(&mut self) -> ImageIndex {
+        self.images.allocate()
+    }
+
+    pub fn update_image(&mut self, index: ImageIndex, image: Image) -> Option<()> {
+        self.images.update(index, image)
+    }
+
+    pub fn get_image(&self, index: ImageIndex) -> Option<&Image> {
+        self.images.get(index)
+    }
+
+    pub fn get_image_mut(&mut self, index: ImageIndex) -> Option<&mut Image> {
+        self.images.get_mut(index)
+    }
+
+    pub fn allocate_sampler(&mut self) -> SamplerIndex {
+        self.samplers.allocate()
+    }
+
+    pub fn update_sampler(&mut self, index: SamplerIndex, sampler: Sampler) -> Option<()> {
+        self.samplers.update(index, sampler)
+    }
+
+    pub fn get_sampler(&self, index: SamplerIndex) -> Option<&Sampler> {
+        self.samplers.get(index)
+    }
+
+    pub fn get_sampler_mut(&mut self, index: SamplerIndex) -> Option<&mut Sampler> {
+        self.samplers.get_mut(index)
+    }
 }
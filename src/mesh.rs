@@ -1,69 +1,262 @@
-use std::collections::HashMap;
+use std::{borrow::Cow, collections::HashMap, ops::Range};
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use serde_repr::{Deserialize_repr, Serialize_repr};
 use serde_with::skip_serializing_none;
 
 use crate::{
-    Model, Vertex,
-    buffer::{AccessorIndex, BufferTypeEx, BufferViewTarget, BufferWriter, MinMax},
+    Model, MorphTarget,
+    buffer::{AccessorIndex, BufferTypeEx, BufferViewTarget, BufferWriter},
+    error::{Error, Result},
     material::MaterialIndex,
+    vertex::VertexSource,
 };
 
+// https://registry.khronos.org/glTF/specs/2.0/glTF-2.0.html#_mesh_primitive_mode
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize_repr, Deserialize_repr)]
+#[repr(usize)]
+pub enum PrimitiveMode {
+    Points = 0,
+    Lines = 1,
+    LineLoop = 2,
+    LineStrip = 3,
+    #[default]
+    Triangles = 4,
+    TriangleStrip = 5,
+    TriangleFan = 6,
+}
+
+impl PrimitiveMode {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Whether `count` vertices (or indices) form a whole number of primitives.
+    pub(crate) fn is_valid_count(&self, count: usize) -> bool {
+        match self {
+            PrimitiveMode::Points => count > 0,
+            PrimitiveMode::Lines => count > 0 && count.is_multiple_of(2),
+            PrimitiveMode::LineLoop | PrimitiveMode::LineStrip => count >= 2,
+            PrimitiveMode::Triangles => count > 0 && count.is_multiple_of(3),
+            PrimitiveMode::TriangleStrip | PrimitiveMode::TriangleFan => count >= 3,
+        }
+    }
+}
+
 #[skip_serializing_none]
-#[derive(Default, Serialize)]
+#[derive(Clone, Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Primitive {
-    pub attributes: HashMap<&'static str, usize>,
-    pub indices: AccessorIndex,
-    pub material: MaterialIndex,
+    pub attributes: HashMap<Cow<'static, str>, usize>,
+    /// Non-indexed primitives draw every vertex in order.
+    pub indices: Option<AccessorIndex>,
+    pub material: Option<MaterialIndex>,
+    #[serde(default, skip_serializing_if = "PrimitiveMode::is_default")]
+    pub mode: PrimitiveMode,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub targets: Vec<HashMap<Cow<'static, str>, usize>>,
 }
 
 #[skip_serializing_none]
-#[derive(Default, Serialize)]
+#[derive(Clone, Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Mesh {
     pub primitives: Vec<Primitive>,
+    pub weights: Option<Vec<f32>>,
+    pub extras: Option<MeshExtras>,
 }
 
-impl Mesh {
-    pub fn new<T: Vertex>(model: &Model<T>, buffer_writer: &mut BufferWriter) -> Self {
-        // Write our vertex and index data
-        let indices_view =
-            buffer_writer.create_view(&model.indices, Some(BufferViewTarget::ElementArrayBuffer));
-        let vertex_attributes = T::write_slices(buffer_writer, &model.vertices);
+// Morph target names aren't part of the core spec, but most tools
+// read them from the mesh extras.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MeshExtras {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub target_names: Vec<String>,
+}
+
+/// Anything that can be written out as a mesh. This is implemented for
+/// references and boxes so models with different vertex types can be
+/// exported together, e.g. as a `&[&dyn MeshSource]`.
+pub trait MeshSource {
+    fn write_mesh(&self, buffer_writer: &mut BufferWriter) -> Result<Mesh>;
+}
+
+impl<V: VertexSource> MeshSource for Model<V> {
+    fn write_mesh(&self, buffer_writer: &mut BufferWriter) -> Result<Mesh> {
+        Mesh::new(self, buffer_writer)
+    }
+}
 
-        let mut mesh_primitives = Vec::new();
+impl<T: MeshSource + ?Sized> MeshSource for &T {
+    fn write_mesh(&self, buffer_writer: &mut BufferWriter) -> Result<Mesh> {
+        (**self).write_mesh(buffer_writer)
+    }
+}
+
+impl<T: MeshSource + ?Sized> MeshSource for Box<T> {
+    fn write_mesh(&self, buffer_writer: &mut BufferWriter) -> Result<Mesh> {
+        (**self).write_mesh(buffer_writer)
+    }
+}
+
+impl Mesh {
+    pub fn new<V: VertexSource>(
+        model: &Model<V>,
+        buffer_writer: &mut BufferWriter,
+    ) -> Result<Self> {
+        // Validate the ranges before anything is written to the buffer
+        let vertex_count = model.vertices.vertex_count();
+        let mut primitive_data = Vec::with_capacity(model.meshes.len());
         for mesh in &model.meshes {
-            let indices = &model.indices[mesh.indices_range.start..mesh.indices_range.end];
-            let (min, max) = u32::find_min_max(indices);
-            let indices_accessor = buffer_writer.create_accessor_with_min_max(
-                indices_view,
-                mesh.indices_range.start * std::mem::size_of::<u32>(),
-                mesh.indices_range.end - mesh.indices_range.start,
-                MinMax { min, max },
-            );
-            mesh_primitives.push((indices_accessor, mesh.texture_index));
+            let vertex_range = mesh.vertex_range.clone().unwrap_or(0..vertex_count);
+            if vertex_range.start > vertex_range.end || vertex_range.end > vertex_count {
+                return Err(Error::InvalidIndex {
+                    kind: "vertex",
+                    index: vertex_range.end,
+                    len: vertex_count,
+                });
+            }
+            let Some(indices_range) = &mesh.indices_range else {
+                primitive_data.push((vertex_range, None));
+                continue;
+            };
+            let indices = model
+                .indices
+                .get(indices_range.clone())
+                .ok_or(Error::InvalidIndex {
+                    kind: "index",
+                    index: indices_range.end,
+                    len: model.indices.len(),
+                })?;
+            // Indices refer to the whole vertex array, but are written
+            // relative to the start of the primitive's vertices
+            let mut relative_indices = Vec::with_capacity(indices.len());
+            for index in indices {
+                let index = *index as usize;
+                if index >= vertex_count {
+                    return Err(Error::InvalidIndex {
+                        kind: "vertex",
+                        index,
+                        len: vertex_count,
+                    });
+                }
+                if !vertex_range.contains(&index) {
+                    return Err(Error::SpecViolation(format!(
+                        "Index {} is outside of the vertex range {:?}",
+                        index, vertex_range
+                    )));
+                }
+                relative_indices.push((index - vertex_range.start) as u32);
+            }
+            primitive_data.push((vertex_range, Some(relative_indices)));
+        }
+        for target in &model.morph_targets {
+            target.check_len(vertex_count)?;
         }
 
-        // Create primitives
-        let attributes = {
-            let mut attributes = HashMap::new();
-            for (name, value) in vertex_attributes {
-                attributes.insert(name, value);
-            }
-            attributes
-        };
+        // Primitives with the same vertex range share their vertex data
+        let mut written_vertices = HashMap::new();
         let mut primitives = Vec::with_capacity(model.meshes.len());
-        for (indices, material) in mesh_primitives {
-            let mut material_index = MaterialIndex::default();
-            material_index.0 = material;
+        for (mesh, (vertex_range, indices)) in model.meshes.iter().zip(primitive_data) {
+            let (attributes, targets) = written_vertices
+                .entry(vertex_range.clone())
+                .or_insert_with(|| {
+                    let attributes: HashMap<_, _> = model
+                        .vertices
+                        .write_attributes(buffer_writer, vertex_range.clone())
+                        .into_iter()
+                        .collect();
+                    let targets: Vec<_> = model
+                        .morph_targets
+                        .iter()
+                        .map(|x| write_morph_target(buffer_writer, x, vertex_range.clone()))
+                        .collect();
+                    (attributes, targets)
+                })
+                .clone();
+            let indices = indices.map(|indices| {
+                let (_, max) = u32::find_min_max(&indices);
+                write_indices(buffer_writer, &indices, max)
+            });
+
+            let material_index = mesh.texture_index.map(MaterialIndex::new);
             primitives.push(Primitive {
-                attributes: attributes.clone(),
+                attributes,
                 indices,
                 material: material_index,
+                mode: mesh.mode,
+                targets,
             });
         }
 
-        Self { primitives }
+        let (weights, extras) = if model.morph_targets.is_empty() {
+            (None, None)
+        } else {
+            let weights = model
+                .morph_targets
+                .iter()
+                .map(|x| x.default_weight)
+                .collect();
+            let extras = if model.morph_targets.iter().any(|x| !x.name.is_empty()) {
+                Some(MeshExtras {
+                    target_names: model.morph_targets.iter().map(|x| x.name.clone()).collect(),
+                })
+            } else {
+                None
+            };
+            (Some(weights), extras)
+        };
+
+        Ok(Self {
+            primitives,
+            weights,
+            extras,
+        })
+    }
+}
+
+// Each primitive gets its own view so it can use the smallest index type.
+// The largest value of each type is reserved for primitive restart.
+fn write_indices(buffer_writer: &mut BufferWriter, indices: &[u32], max: u32) -> AccessorIndex {
+    let target = Some(BufferViewTarget::ElementArrayBuffer);
+    let pair = if max < u8::MAX as u32 {
+        let indices: Vec<_> = indices.iter().map(|x| *x as u8).collect();
+        buffer_writer.create_view_and_accessor_with_min_max(&indices, target)
+    } else if max < u16::MAX as u32 {
+        let indices: Vec<_> = indices.iter().map(|x| *x as u16).collect();
+        buffer_writer.create_view_and_accessor_with_min_max(&indices, target)
+    } else {
+        buffer_writer.create_view_and_accessor_with_min_max(indices, target)
+    };
+    pair.accessor
+}
+
+fn write_morph_target(
+    buffer_writer: &mut BufferWriter,
+    target: &MorphTarget,
+    range: Range<usize>,
+) -> HashMap<Cow<'static, str>, usize> {
+    let mut attributes = HashMap::new();
+    // Morph target positions are required to have bounds
+    if !target.positions.is_empty() {
+        let pair = buffer_writer.create_view_and_accessor_with_min_max(
+            &target.positions[range.clone()],
+            Some(BufferViewTarget::ArrayBuffer),
+        );
+        attributes.insert(Cow::Borrowed("POSITION"), pair.accessor.0);
+    }
+    if !target.normals.is_empty() {
+        let pair = buffer_writer.create_view_and_accessor(
+            &target.normals[range.clone()],
+            Some(BufferViewTarget::ArrayBuffer),
+        );
+        attributes.insert(Cow::Borrowed("NORMAL"), pair.accessor.0);
+    }
+    if !target.tangents.is_empty() {
+        let pair = buffer_writer
+            .create_view_and_accessor(&target.tangents[range], Some(BufferViewTarget::ArrayBuffer));
+        attributes.insert(Cow::Borrowed("TANGENT"), pair.accessor.0);
     }
+    attributes
 }
@@ -1,32 +1,61 @@
-use glam::{Vec3, Vec4};
-use serde::Serialize;
+use glam::{Mat4, Quat, Vec3};
+use serde::{Deserialize, Serialize};
 use serde_with::skip_serializing_none;
 
-use crate::storage::{Storage, StorageIndex};
+use crate::{
+    error::Result,
+    storage::{Storage, StorageIndex},
+    transform::ComponentTransform,
+};
 
 use super::skin::SkinIndex;
 
-#[derive(Copy, Clone, Debug, Serialize)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub struct MeshIndex(pub usize);
 pub type NodeIndex = StorageIndex<Node>;
 
 #[skip_serializing_none]
-#[derive(Clone, Debug, Default, Serialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Node {
     pub mesh: Option<MeshIndex>,
     pub skin: Option<SkinIndex>,
     pub name: Option<String>,
+    // Either `matrix` or any of translation/rotation/scale may be set, not both
+    pub matrix: Option<Mat4>,
     pub translation: Option<Vec3>,
-    pub rotation: Option<Vec4>,
-    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub rotation: Option<Quat>,
+    pub scale: Option<Vec3>,
+    pub weights: Option<Vec<f32>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub children: Vec<NodeIndex>,
 }
 
-#[derive(Clone, Default, Serialize)]
+impl Node {
+    pub fn set_transform(&mut self, transform: &ComponentTransform) {
+        self.matrix = None;
+        self.translation = Some(transform.translation);
+        self.rotation = Some(transform.get_rotation_quat());
+        // Unit scale is the default, so don't bother writing it out
+        self.scale = (transform.scale != Vec3::ONE).then_some(transform.scale);
+    }
+
+    pub fn local_transform(&self) -> Mat4 {
+        match self.matrix {
+            Some(matrix) => matrix,
+            None => Mat4::from_scale_rotation_translation(
+                self.scale.unwrap_or(Vec3::ONE),
+                self.rotation.unwrap_or(Quat::IDENTITY),
+                self.translation.unwrap_or(Vec3::ZERO),
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
 #[serde(transparent)]
 pub struct Nodes {
-    nodes: Storage<Node>,
+    pub(crate) nodes: Storage<Node>,
 }
 
 impl Nodes {
@@ -40,11 +69,156 @@ impl Nodes {
         self.nodes.allocate_with(node)
     }
 
-    pub fn write_nodes(&self) -> String {
-        serde_json::to_string_pretty(&self.nodes).unwrap()
+    pub fn allocate(&mut self) -> NodeIndex {
+        self.nodes.allocate()
+    }
+
+    pub fn update(&mut self, index: NodeIndex, node: Node) -> Option<()> {
+        self.nodes.update(index, node)
+    }
+
+    pub fn get(&self, index: NodeIndex) -> Option<&Node> {
+        self.nodes.get(index)
+    }
+
+    pub fn get_mut(&mut self, index: NodeIndex) -> Option<&mut Node> {
+        self.nodes.get_mut(index)
+    }
+
+    pub fn write_nodes(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(&self.nodes)?)
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
     }
 
     pub fn is_empty(&self) -> bool {
         self.nodes.is_empty()
     }
+
+    /// The parent of every node, by index. Children that don't exist are
+    /// ignored. Parents aren't stored, since `children` can be changed
+    /// through `get_mut`, so compute this once when looking up many nodes.
+    pub fn parents(&self) -> Vec<Option<NodeIndex>> {
+        let mut parents = vec![None; self.nodes.len()];
+        for (i, node) in self.nodes.iter().enumerate() {
+            for child in &node.children {
+                if let Some(parent) = parents.get_mut(child.0) {
+                    *parent = Some(NodeIndex::new(i));
+                }
+            }
+        }
+        parents
+    }
+
+    /// Searches every node for the parent of `node`. Use `parents` to look
+    /// up many nodes.
+    pub fn parent(&self, node: NodeIndex) -> Option<NodeIndex> {
+        self.nodes
+            .iter()
+            .position(|x| x.children.iter().any(|x| x.0 == node.0))
+            .map(NodeIndex::new)
+    }
+
+    /// Iterates the children of `node`, which is empty if `node` doesn't exist.
+    pub fn children(&self, node: NodeIndex) -> impl Iterator<Item = NodeIndex> + '_ {
+        self.nodes
+            .get(node)
+            .into_iter()
+            .flat_map(|x| x.children.iter().copied())
+    }
+
+    pub fn is_root(&self, node: NodeIndex) -> bool {
+        node.0 < self.nodes.len() && self.parent(node).is_none()
+    }
+
+    /// The nodes that aren't the child of any other node.
+    pub fn roots(&self) -> Vec<NodeIndex> {
+        self.parents()
+            .iter()
+            .enumerate()
+            .filter(|(_, parent)| parent.is_none())
+            .map(|(i, _)| NodeIndex::new(i))
+            .collect()
+    }
+
+    /// Visits `root` and its descendants depth first, parents before their
+    /// children. Each node is only visited once, even if the hierarchy has
+    /// loops.
+    pub fn depth_first(&self, root: NodeIndex) -> Vec<NodeIndex> {
+        let mut visited = vec![false; self.nodes.len()];
+        let mut order = Vec::new();
+        let mut stack = vec![root];
+        while let Some(node) = stack.pop() {
+            if node.0 >= self.nodes.len() || visited[node.0] {
+                continue;
+            }
+            visited[node.0] = true;
+            order.push(node);
+            // Reversed so the children are visited in order
+            stack.extend(self.children(node).collect::<Vec<_>>().into_iter().rev());
+        }
+        order
+    }
+
+    /// The transform from `node` to the scene, combining the local
+    /// transforms of the node and its ancestors. Use
+    /// `world_transform_with_parents` for many nodes.
+    pub fn world_transform(&self, node: NodeIndex) -> Option<Mat4> {
+        self.world_transform_with_parents(&self.parents(), node)
+    }
+
+    /// Same as `world_transform`, but with `parents` computed up front.
+    pub fn world_transform_with_parents(
+        &self,
+        parents: &[Option<NodeIndex>],
+        node: NodeIndex,
+    ) -> Option<Mat4> {
+        self.nodes.get(node)?;
+        let nodes = self.nodes.as_slice();
+        let mut transform = Mat4::IDENTITY;
+        let mut current = Some(node);
+        // Give up on hierarchies with loops instead of walking forever
+        for _ in 0..=nodes.len() {
+            let Some(index) = current else {
+                break;
+            };
+            let Some(node) = nodes.get(index.0) else {
+                break;
+            };
+            transform = node.local_transform() * transform;
+            current = parents.get(index.0).copied().flatten();
+        }
+        Some(transform)
+    }
+
+    /// The closest node that is an ancestor of (or one of) every node in
+    /// `nodes`, if they share one.
+    pub(crate) fn common_root_with_parents(
+        parents: &[Option<NodeIndex>],
+        nodes: &[NodeIndex],
+    ) -> Option<NodeIndex> {
+        let ancestors = |node: NodeIndex| {
+            let mut chain = vec![node];
+            while let Some(parent) = parents.get(chain[chain.len() - 1].0).copied().flatten() {
+                if chain.iter().any(|x| x.0 == parent.0) {
+                    break;
+                }
+                chain.push(parent);
+            }
+            chain
+        };
+
+        let (first, rest) = nodes.split_first()?;
+        let mut candidates = ancestors(*first);
+        for node in rest {
+            let chain = ancestors(*node);
+            let common = candidates
+                .iter()
+                .position(|x| chain.iter().any(|y| y.0 == x.0))?;
+            candidates.drain(..common);
+        }
+        candidates.first().copied()
+    }
 }
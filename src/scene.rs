@@ -0,0 +1,74 @@
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+
+use crate::{
+    node::NodeIndex,
+    storage::{Storage, StorageIndex},
+};
+
+pub type SceneIndex = StorageIndex<Scene>;
+
+#[skip_serializing_none]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Scene {
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub nodes: Vec<NodeIndex>,
+}
+
+#[skip_serializing_none]
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Scenes {
+    #[serde(rename = "scene")]
+    pub(crate) default_scene: Option<SceneIndex>,
+    #[serde(default, skip_serializing_if = "Storage::is_empty")]
+    pub(crate) scenes: Storage<Scene>,
+}
+
+impl Scenes {
+    pub fn new() -> Self {
+        Self {
+            default_scene: None,
+            scenes: Storage::new(),
+        }
+    }
+
+    pub fn add_scene(&mut self, scene: Scene) -> SceneIndex {
+        self.scenes.allocate_with(scene)
+    }
+
+    pub fn allocate(&mut self) -> SceneIndex {
+        self.scenes.allocate()
+    }
+
+    pub fn update(&mut self, index: SceneIndex, scene: Scene) -> Option<()> {
+        self.scenes.update(index, scene)
+    }
+
+    pub fn get(&self, index: SceneIndex) -> Option<&Scene> {
+        self.scenes.get(index)
+    }
+
+    pub fn get_mut(&mut self, index: SceneIndex) -> Option<&mut Scene> {
+        self.scenes.get_mut(index)
+    }
+
+    /// The scene to display when the document is loaded. If this isn't
+    /// set, the application is free to pick one (or none).
+    pub fn set_default_scene(&mut self, scene: Option<SceneIndex>) {
+        self.default_scene = scene;
+    }
+
+    pub fn default_scene(&self) -> Option<SceneIndex> {
+        self.default_scene
+    }
+
+    pub fn len(&self) -> usize {
+        self.scenes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scenes.is_empty()
+    }
+}
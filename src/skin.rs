@@ -1,22 +1,84 @@
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
 
-use crate::storage::{Storage, StorageIndex};
+use crate::{
+    buffer::BufferWriter,
+    error::{Error, Result},
+    storage::{Storage, StorageIndex},
+};
 
-use super::{buffer::AccessorIndex, node::NodeIndex};
+use super::{
+    buffer::AccessorIndex,
+    node::{NodeIndex, Nodes},
+};
 
 pub type SkinIndex = StorageIndex<Skin>;
 
-#[derive(Clone, Debug, Default, Serialize)]
+#[skip_serializing_none]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Skin {
-    pub inverse_bind_matrices: AccessorIndex,
+    /// Each joint's inverse bind matrix is the identity when `None`.
+    pub inverse_bind_matrices: Option<AccessorIndex>,
+    /// The closest common root of the joints, or one of its ancestors.
+    pub skeleton: Option<NodeIndex>,
     pub joints: Vec<NodeIndex>,
 }
 
-#[derive(Clone, Default, Serialize)]
+impl Skin {
+    /// Creates a skin bound to the pose `nodes` are currently in. The
+    /// inverse bind matrices are computed from the world transforms of the
+    /// joints and `skeleton` is set to their closest common root.
+    pub fn from_bind_pose(
+        joints: Vec<NodeIndex>,
+        nodes: &Nodes,
+        buffer_writer: &mut BufferWriter,
+    ) -> Result<Self> {
+        if joints.is_empty() {
+            return Err(Error::SpecViolation(
+                "A skin needs at least one joint".to_owned(),
+            ));
+        }
+        for joint in &joints {
+            if joint.0 >= nodes.len() {
+                return Err(Error::DanglingReference {
+                    from: "skin",
+                    to: "node",
+                    index: joint.0,
+                });
+            }
+        }
+
+        let parents = nodes.parents();
+        let skeleton = Nodes::common_root_with_parents(&parents, &joints).ok_or_else(|| {
+            Error::SpecViolation("The joints of a skin must share a common root".to_owned())
+        })?;
+        let inverse_bind_matrices: Vec<_> = joints
+            .iter()
+            .map(|x| {
+                // Joints were checked above
+                nodes
+                    .world_transform_with_parents(&parents, *x)
+                    .unwrap_or_default()
+                    .inverse()
+            })
+            .collect();
+        let inverse_bind_matrices = buffer_writer
+            .create_view_and_accessor(&inverse_bind_matrices, None)
+            .accessor;
+
+        Ok(Self {
+            inverse_bind_matrices: Some(inverse_bind_matrices),
+            skeleton: Some(skeleton),
+            joints,
+        })
+    }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
 #[serde(transparent)]
 pub struct Skins {
-    skins: Storage<Skin>,
+    pub(crate) skins: Storage<Skin>,
 }
 
 impl Skins {
@@ -30,6 +92,26 @@ impl Skins {
         self.skins.allocate_with(skin)
     }
 
+    pub fn allocate(&mut self) -> SkinIndex {
+        self.skins.allocate()
+    }
+
+    pub fn update(&mut self, index: SkinIndex, skin: Skin) -> Option<()> {
+        self.skins.update(index, skin)
+    }
+
+    pub fn get(&self, index: SkinIndex) -> Option<&Skin> {
+        self.skins.get(index)
+    }
+
+    pub fn get_mut(&mut self, index: SkinIndex) -> Option<&mut Skin> {
+        self.skins.get_mut(index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.skins.len()
+    }
+
     pub fn is_empty(&self) -> bool {
         self.skins.is_empty()
     }
@@ -1,9 +1,15 @@
-use serde::{Serialize, Serializer};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use crate::add_and_get_index;
 
 pub struct StorageIndex<T: Sized + Default + Serialize>(pub usize, std::marker::PhantomData<T>);
 
+impl<T: Sized + Default + Serialize> StorageIndex<T> {
+    pub(crate) fn new(index: usize) -> Self {
+        Self(index, std::marker::PhantomData)
+    }
+}
+
 // These are implemented manually as a workaround for rust-lang/rust#26925
 impl<T: Sized + Default + Serialize> Copy for StorageIndex<T> {}
 impl<T: Sized + Default + Serialize> Clone for StorageIndex<T> {
@@ -32,8 +38,17 @@ impl<T: Sized + Default + Serialize> Serialize for StorageIndex<T> {
         serializer.serialize_u64(self.0 as u64)
     }
 }
+impl<'de, T: Sized + Default + Serialize> Deserialize<'de> for StorageIndex<T> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let index = usize::deserialize(deserializer)?;
+        Ok(Self(index, std::marker::PhantomData))
+    }
+}
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(transparent)]
 pub struct Storage<T: Sized + Default + Serialize> {
     items: Vec<T>,
@@ -50,6 +65,9 @@ impl<T: Sized + Default + Serialize> Storage<T> {
         }
     }
 
+    /// Reserves a default item so it can be referred to before it is
+    /// filled in with `update`. The collections that wrap a `Storage`
+    /// (`Nodes`, `Skins`, `MaterialData`, ...) forward to this.
     pub fn allocate(&mut self) -> StorageIndex<T> {
         let item = T::default();
         let index = add_and_get_index(&mut self.items, item);
@@ -67,6 +85,26 @@ impl<T: Sized + Default + Serialize> Storage<T> {
         Some(())
     }
 
+    pub fn get(&self, index: StorageIndex<T>) -> Option<&T> {
+        self.items.get(index.0)
+    }
+
+    pub fn get_mut(&mut self, index: StorageIndex<T>) -> Option<&mut T> {
+        self.items.get_mut(index.0)
+    }
+
+    pub(crate) fn as_slice(&self) -> &[T] {
+        &self.items
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, T> {
+        self.items.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> std::slice::IterMut<'_, T> {
+        self.items.iter_mut()
+    }
+
     pub fn len(&self) -> usize {
         self.items.len()
     }
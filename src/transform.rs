@@ -1,8 +1,17 @@
-use glam::{EulerRot, Mat4, Quat, Vec3};
+use glam::{EulerRot, Mat3, Mat4, Quat, Vec3, Vec4};
+
+use crate::{
+    Model,
+    error::{Error, Result},
+    mesh::PrimitiveMode,
+    node::Nodes,
+    vertex::VertexSource,
+};
 
 pub struct ComponentTransform {
     pub translation: Vec3,
     pub rotation: Vec3,
+    pub scale: Vec3,
 }
 
 impl ComponentTransform {
@@ -10,18 +19,440 @@ impl ComponentTransform {
         Self {
             translation,
             rotation,
+            scale: Vec3::ONE,
+        }
+    }
+
+    pub fn with_scale(translation: Vec3, rotation: Vec3, scale: Vec3) -> Self {
+        Self {
+            translation,
+            rotation,
+            scale,
         }
     }
 
+    /// Decomposes an affine transform. Shear can't be represented and is lost.
+    pub fn from_mat4(matrix: Mat4) -> Self {
+        let (scale, rotation, translation) = matrix.to_scale_rotation_translation();
+        Self::with_scale(translation, euler_from_quat(rotation), scale)
+    }
+
     pub fn get_rotation_quat(&self) -> Quat {
         quat_from_euler(self.rotation)
     }
 
     pub fn to_mat4(&self) -> Mat4 {
-        Mat4::from_rotation_translation(self.get_rotation_quat(), self.translation)
+        Mat4::from_scale_rotation_translation(
+            self.scale,
+            self.get_rotation_quat(),
+            self.translation,
+        )
     }
 }
 
+/// Builds a rotation from angles about the x, y and z axes, applied as yaw
+/// (y), pitch (x) then roll (z), i.e. `EulerRot::YXZ`. This is the
+/// convention `ComponentTransform::rotation` uses. Angles from a source with
+/// another convention should go through `CoordinateSystem::convert_euler`.
 pub fn quat_from_euler(euler: Vec3) -> Quat {
     Quat::from_euler(EulerRot::YXZ, euler.y, euler.x, euler.z).normalize()
 }
+
+pub fn euler_from_quat(quat: Quat) -> Vec3 {
+    let (y, x, z) = quat.to_euler(EulerRot::YXZ);
+    Vec3::new(x, y, z)
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Axis {
+    PositiveX,
+    NegativeX,
+    PositiveY,
+    NegativeY,
+    PositiveZ,
+    NegativeZ,
+}
+
+impl Axis {
+    fn to_vec3(self) -> Vec3 {
+        match self {
+            Axis::PositiveX => Vec3::X,
+            Axis::NegativeX => Vec3::NEG_X,
+            Axis::PositiveY => Vec3::Y,
+            Axis::NegativeY => Vec3::NEG_Y,
+            Axis::PositiveZ => Vec3::Z,
+            Axis::NegativeZ => Vec3::NEG_Z,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Handedness {
+    Right,
+    Left,
+}
+
+/// The axes and units of source data, used to convert it to glTF's
+/// coordinate system: +Y up, +Z forward, right handed and in meters.
+/// https://registry.khronos.org/glTF/specs/2.0/glTF-2.0.html#coordinate-system-and-units
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CoordinateSystem {
+    up: Axis,
+    forward: Axis,
+    handedness: Handedness,
+    unit_scale: f32,
+}
+
+impl CoordinateSystem {
+    pub const GLTF: Self = Self {
+        up: Axis::PositiveY,
+        forward: Axis::PositiveZ,
+        handedness: Handedness::Right,
+        unit_scale: 1.0,
+    };
+
+    /// `unit_scale` is the size of one source unit in meters.
+    pub fn new(up: Axis, forward: Axis, handedness: Handedness, unit_scale: f32) -> Result<Self> {
+        if up.to_vec3().dot(forward.to_vec3()) != 0.0 {
+            return Err(Error::SpecViolation(format!(
+                "The up axis ({:?}) and forward axis ({:?}) must be perpendicular",
+                up, forward
+            )));
+        }
+        if !unit_scale.is_finite() || unit_scale <= 0.0 {
+            return Err(Error::SpecViolation(format!(
+                "Unit scale must be positive, not {}",
+                unit_scale
+            )));
+        }
+        Ok(Self {
+            up,
+            forward,
+            handedness,
+            unit_scale,
+        })
+    }
+
+    pub fn up(&self) -> Axis {
+        self.up
+    }
+
+    pub fn forward(&self) -> Axis {
+        self.forward
+    }
+
+    pub fn handedness(&self) -> Handedness {
+        self.handedness
+    }
+
+    pub fn unit_scale(&self) -> f32 {
+        self.unit_scale
+    }
+
+    /// Whether converting mirrors the data, which flips triangle winding
+    /// and tangent handedness.
+    pub fn is_mirrored(&self) -> bool {
+        self.handedness != Handedness::Right
+    }
+
+    // Maps source axes to glTF axes, where +X is left of the forward axis
+    fn basis(&self) -> Mat3 {
+        let up = self.up.to_vec3();
+        let forward = self.forward.to_vec3();
+        let left = match self.handedness {
+            Handedness::Right => up.cross(forward),
+            Handedness::Left => forward.cross(up),
+        };
+        Mat3::from_cols(left, up, forward).transpose()
+    }
+
+    pub fn convert_position(&self, position: Vec3) -> Vec3 {
+        self.basis() * position * self.unit_scale
+    }
+
+    /// Converts a normal or other direction. Directions aren't scaled.
+    pub fn convert_direction(&self, direction: Vec3) -> Vec3 {
+        self.basis() * direction
+    }
+
+    /// Converts a tangent, flipping the sign of the bitangent (w) if the
+    /// conversion mirrors.
+    pub fn convert_tangent(&self, tangent: Vec4) -> Vec4 {
+        let w = if self.is_mirrored() {
+            -tangent.w
+        } else {
+            tangent.w
+        };
+        self.convert_direction(tangent.truncate()).extend(w)
+    }
+
+    pub fn convert_rotation(&self, rotation: Quat) -> Quat {
+        let basis = self.basis();
+        Quat::from_mat3(&(basis * Mat3::from_quat(rotation) * basis.transpose())).normalize()
+    }
+
+    /// Converts a rotation given as Euler angles in the source system. The
+    /// angles are listed in the order of the axes of `order`, as with
+    /// `Quat::from_euler`.
+    pub fn convert_euler(&self, order: EulerRot, angles: Vec3) -> Quat {
+        self.convert_rotation(Quat::from_euler(order, angles.x, angles.y, angles.z))
+    }
+
+    /// Converts a scale. Each axis is only swapped, never negated.
+    pub fn convert_scale(&self, scale: Vec3) -> Vec3 {
+        self.convert_direction(scale).abs()
+    }
+
+    pub fn convert_matrix(&self, matrix: Mat4) -> Mat4 {
+        let conversion = Mat4::from_mat3(self.basis() * self.unit_scale);
+        conversion * matrix * conversion.inverse()
+    }
+
+    pub fn convert_transform(&self, transform: &ComponentTransform) -> ComponentTransform {
+        let rotation = self.convert_rotation(transform.get_rotation_quat());
+        ComponentTransform::with_scale(
+            self.convert_position(transform.translation),
+            euler_from_quat(rotation),
+            self.convert_scale(transform.scale),
+        )
+    }
+
+    /// Converts the transforms of every node. Skins built with
+    /// `Skin::from_bind_pose` should be created after converting.
+    /// Animation keyframes need to be converted before they are written.
+    pub fn convert_nodes(&self, nodes: &mut Nodes) {
+        for node in nodes.nodes.iter_mut() {
+            node.matrix = node.matrix.map(|x| self.convert_matrix(x));
+            node.translation = node.translation.map(|x| self.convert_position(x));
+            node.rotation = node.rotation.map(|x| self.convert_rotation(x));
+            node.scale = node.scale.map(|x| self.convert_scale(x));
+        }
+    }
+
+    /// Converts the vertices and morph targets of `model`. If the
+    /// conversion mirrors, triangle winding is flipped to keep the same
+    /// front faces, which gives every triangle mesh its own indices.
+    pub fn convert_model<V: VertexSource>(&self, model: &mut Model<V>) -> Result<()> {
+        let vertex_count = model.vertices.vertex_count();
+        for target in &model.morph_targets {
+            target.check_len(vertex_count)?;
+        }
+
+        model.vertices.convert_coordinates(self);
+        let convert = |data: &mut Vec<[f32; 3]>, convert: &dyn Fn(Vec3) -> Vec3| {
+            for value in data {
+                *value = convert(Vec3::from_array(*value)).to_array();
+            }
+        };
+        for target in &mut model.morph_targets {
+            convert(&mut target.positions, &|x| self.convert_position(x));
+            convert(&mut target.normals, &|x| self.convert_direction(x));
+            convert(&mut target.tangents, &|x| self.convert_direction(x));
+        }
+
+        if self.is_mirrored() {
+            let mut indices = Vec::with_capacity(model.indices.len());
+            for mesh in &mut model.meshes {
+                let is_triangles = matches!(
+                    mesh.mode,
+                    PrimitiveMode::Triangles
+                        | PrimitiveMode::TriangleStrip
+                        | PrimitiveMode::TriangleFan
+                );
+                let mesh_indices = match &mesh.indices_range {
+                    Some(indices_range) => model
+                        .indices
+                        .get(indices_range.clone())
+                        .ok_or(Error::InvalidIndex {
+                            kind: "index",
+                            index: indices_range.end,
+                            len: model.indices.len(),
+                        })?
+                        .to_vec(),
+                    None if is_triangles => {
+                        let vertex_range = mesh.vertex_range.clone().unwrap_or(0..vertex_count);
+                        (vertex_range.start as u32..vertex_range.end as u32).collect()
+                    }
+                    None => continue,
+                };
+                let index_start = indices.len();
+                indices.extend(flip_winding(mesh.mode, mesh_indices));
+                mesh.indices_range = Some(index_start..indices.len());
+            }
+            model.indices = indices;
+        }
+        Ok(())
+    }
+}
+
+// Reorders the indices so that every triangle faces the other way
+fn flip_winding(mode: PrimitiveMode, mut indices: Vec<u32>) -> Vec<u32> {
+    match mode {
+        PrimitiveMode::Triangles => {
+            for triangle in indices.chunks_exact_mut(3) {
+                triangle.swap(1, 2);
+            }
+        }
+        // Every other strip triangle is already reversed, so reversing the
+        // whole strip only works for an odd length. Otherwise a leading
+        // degenerate triangle shifts the rest by one.
+        PrimitiveMode::TriangleStrip => {
+            if !indices.len().is_multiple_of(2) {
+                indices.reverse();
+            } else if let Some(first) = indices.first().copied() {
+                indices.insert(0, first);
+            }
+        }
+        PrimitiveMode::TriangleFan => {
+            if let Some((_, rest)) = indices.split_first_mut() {
+                rest.reverse();
+            }
+        }
+        PrimitiveMode::Points
+        | PrimitiveMode::Lines
+        | PrimitiveMode::LineLoop
+        | PrimitiveMode::LineStrip => {}
+    }
+    indices
+}
+
+/// Vertex attribute values that change with the coordinate system. Only
+/// POSITION, NORMAL and TANGENT are converted.
+pub trait ConvertAttribute {
+    fn convert_attribute(&mut self, _name: &str, _system: &CoordinateSystem) {}
+}
+
+impl ConvertAttribute for Vec3 {
+    fn convert_attribute(&mut self, name: &str, system: &CoordinateSystem) {
+        match name {
+            "POSITION" => *self = system.convert_position(*self),
+            "NORMAL" => *self = system.convert_direction(*self),
+            _ => {}
+        }
+    }
+}
+
+impl ConvertAttribute for [f32; 3] {
+    fn convert_attribute(&mut self, name: &str, system: &CoordinateSystem) {
+        let mut value = Vec3::from_array(*self);
+        value.convert_attribute(name, system);
+        *self = value.to_array();
+    }
+}
+
+impl ConvertAttribute for Vec4 {
+    fn convert_attribute(&mut self, name: &str, system: &CoordinateSystem) {
+        if name == "TANGENT" {
+            *self = system.convert_tangent(*self);
+        }
+    }
+}
+
+impl ConvertAttribute for [f32; 4] {
+    fn convert_attribute(&mut self, name: &str, system: &CoordinateSystem) {
+        let mut value = Vec4::from_array(*self);
+        value.convert_attribute(name, system);
+        *self = value.to_array();
+    }
+}
+
+impl ConvertAttribute for u8 {}
+impl ConvertAttribute for u16 {}
+impl ConvertAttribute for u32 {}
+impl ConvertAttribute for f32 {}
+impl ConvertAttribute for [f32; 2] {}
+impl ConvertAttribute for [u8; 4] {}
+impl ConvertAttribute for [u16; 4] {}
+impl ConvertAttribute for Mat4 {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Triangles as drawn, rotated so the smallest index is first, without
+    // degenerate triangles
+    fn triangles(mode: PrimitiveMode, indices: &[u32]) -> Vec<[u32; 3]> {
+        let triangles: Vec<[u32; 3]> = match mode {
+            PrimitiveMode::Triangles => indices
+                .chunks_exact(3)
+                .map(|x| [x[0], x[1], x[2]])
+                .collect(),
+            PrimitiveMode::TriangleStrip => (0..indices.len() - 2)
+                .map(|i| {
+                    let odd = i % 2;
+                    [indices[i], indices[i + 1 + odd], indices[i + 2 - odd]]
+                })
+                .collect(),
+            PrimitiveMode::TriangleFan => (0..indices.len() - 2)
+                .map(|i| [indices[i + 1], indices[i + 2], indices[0]])
+                .collect(),
+            _ => unreachable!(),
+        };
+        let mut triangles: Vec<_> = triangles
+            .into_iter()
+            .filter(|[a, b, c]| a != b && b != c && a != c)
+            .map(|mut x| {
+                let min = (0..3).min_by_key(|i| x[*i]).unwrap();
+                x.rotate_left(min);
+                x
+            })
+            .collect();
+        triangles.sort();
+        triangles
+    }
+
+    fn check_flipped(mode: PrimitiveMode, indices: &[u32]) {
+        let mut expected: Vec<_> = triangles(mode, indices)
+            .into_iter()
+            .map(|[a, b, c]| [a, c, b])
+            .collect();
+        expected.sort();
+        let flipped = flip_winding(mode, indices.to_vec());
+        assert!(mode.is_valid_count(flipped.len()));
+        assert_eq!(
+            triangles(mode, &flipped),
+            expected,
+            "{:?} {:?}",
+            mode,
+            indices
+        );
+    }
+
+    #[test]
+    fn flip_winding_reverses_every_triangle() {
+        check_flipped(PrimitiveMode::Triangles, &[0, 1, 2, 3, 4, 5]);
+        check_flipped(PrimitiveMode::TriangleStrip, &[0, 1, 2]);
+        check_flipped(PrimitiveMode::TriangleStrip, &[0, 1, 2, 3]);
+        check_flipped(PrimitiveMode::TriangleStrip, &[0, 1, 2, 3, 4]);
+        check_flipped(PrimitiveMode::TriangleStrip, &[0, 1, 2, 3, 4, 5]);
+        check_flipped(PrimitiveMode::TriangleFan, &[0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn flip_winding_keeps_lines() {
+        let indices = vec![0, 1, 2, 3];
+        assert_eq!(
+            flip_winding(PrimitiveMode::LineStrip, indices.clone()),
+            indices
+        );
+    }
+
+    #[test]
+    fn convert_euler_from_z_up() {
+        // Z up, X forward: source (x, y, z) is glTF (z, x, y), and yaw about
+        // the source Z axis is yaw about glTF's Y axis
+        let system =
+            CoordinateSystem::new(Axis::PositiveZ, Axis::PositiveX, Handedness::Right, 1.0)
+                .unwrap();
+        let (roll, pitch, yaw) = (0.1, 0.2, 0.3);
+        let rotation = system.convert_euler(EulerRot::ZYX, Vec3::new(yaw, pitch, roll));
+        let expected = quat_from_euler(Vec3::new(pitch, yaw, roll));
+        assert!(rotation.angle_between(expected) < 1e-5);
+
+        let position = Vec3::new(1.0, 2.0, 3.0);
+        assert_eq!(
+            system.convert_position(position),
+            Vec3::new(position.y, position.z, position.x)
+        );
+    }
+}
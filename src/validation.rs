@@ -0,0 +1,747 @@
+use crate::{
+    animation::{AnimationInterpolation, AnimationTarget},
+    buffer::{AccessorComponentType, AccessorDataType},
+    document::GltfDocument,
+    material::AlphaMode,
+    node::Nodes,
+    vertex::check_attribute_format,
+};
+
+/// A problem found while validating a document. Where possible the codes
+/// match the ones reported by the Khronos glTF validator.
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub code: &'static str,
+    /// JSON pointer to the offending value (e.g. `/skins/0/joints/3`).
+    pub pointer: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}: {}", self.code, self.pointer, self.message)
+    }
+}
+
+struct Validator<'a, 'b> {
+    document: &'b GltfDocument<'a>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Validator<'_, '_> {
+    fn report(&mut self, code: &'static str, pointer: String, message: String) {
+        self.diagnostics.push(Diagnostic {
+            code,
+            pointer,
+            message,
+        });
+    }
+
+    fn check_reference(&mut self, pointer: String, to: &str, index: usize, len: usize) -> bool {
+        if index >= len {
+            self.report(
+                "UNRESOLVED_REFERENCE",
+                pointer,
+                format!("Unresolved reference to {} {}", to, index),
+            );
+            false
+        } else {
+            true
+        }
+    }
+
+    /// The number of morph targets of the node's mesh, or 0 if it has none.
+    fn morph_target_count(&self, node: usize) -> usize {
+        self.document
+            .nodes
+            .nodes
+            .as_slice()
+            .get(node)
+            .and_then(|x| x.mesh)
+            .and_then(|x| self.document.meshes.get(x.0))
+            .and_then(|x| x.primitives.first())
+            .map(|x| x.targets.len())
+            .unwrap_or(0)
+    }
+
+    // https://registry.khronos.org/glTF/specs/2.0/glTF-2.0.html#data-alignment
+    // Vertex attributes must start on, and be strided by, a multiple of 4
+    // bytes. Missing references are reported elsewhere.
+    fn is_vertex_aligned(&self, accessor: usize) -> bool {
+        let buffer_writer = &self.document.buffer_writer;
+        let Some(accessor) = buffer_writer.accessors.as_slice().get(accessor) else {
+            return true;
+        };
+        let Some(view) = accessor
+            .buffer_view
+            .and_then(|x| buffer_writer.views.as_slice().get(x))
+        else {
+            return true;
+        };
+        let offset = view
+            .byte_offset
+            .wrapping_add(accessor.byte_offset.unwrap_or(0));
+        let stride = view.stride.unwrap_or(accessor.element_size());
+        offset.is_multiple_of(4) && stride.is_multiple_of(4)
+    }
+
+    fn validate_buffer_views(&mut self) {
+        let buffer_len = self.document.buffer_writer.buffer_len();
+        for (i, view) in self.document.buffer_writer.views.iter().enumerate() {
+            if !self.check_reference(
+                format!("/bufferViews/{}/buffer", i),
+                "buffer",
+                view.buffer,
+                self.document.buffers.len(),
+            ) {
+                continue;
+            }
+            if view
+                .byte_offset
+                .checked_add(view.byte_len)
+                .is_none_or(|x| x > buffer_len)
+            {
+                self.report(
+                    "BUFFER_VIEW_TOO_LONG",
+                    format!("/bufferViews/{}", i),
+                    format!(
+                        "Buffer view of {} bytes at offset {} doesn't fit in the buffer ({} bytes)",
+                        view.byte_len, view.byte_offset, buffer_len
+                    ),
+                );
+            }
+        }
+    }
+
+    fn validate_accessors(&mut self) {
+        let views = &self.document.buffer_writer.views;
+        for (i, accessor) in self.document.buffer_writer.accessors.iter().enumerate() {
+            if accessor.count == 0 {
+                self.report(
+                    "VALUE_NOT_IN_RANGE",
+                    format!("/accessors/{}/count", i),
+                    "Accessor count must be at least 1".to_owned(),
+                );
+            }
+
+            if let Some(sparse) = &accessor.sparse {
+                if sparse.count == 0 || sparse.count > accessor.count {
+                    self.report(
+                        "ACCESSOR_SPARSE_COUNT_OUT_OF_RANGE",
+                        format!("/accessors/{}/sparse/count", i),
+                        format!(
+                            "Sparse count {} must be between 1 and {}",
+                            sparse.count, accessor.count
+                        ),
+                    );
+                }
+                self.check_reference(
+                    format!("/accessors/{}/sparse/indices/bufferView", i),
+                    "buffer view",
+                    sparse.indices.buffer_view,
+                    views.len(),
+                );
+                self.check_reference(
+                    format!("/accessors/{}/sparse/values/bufferView", i),
+                    "buffer view",
+                    sparse.values.buffer_view,
+                    views.len(),
+                );
+            }
+
+            let component_size = accessor.component_ty.size();
+            let byte_offset = accessor.byte_offset.unwrap_or(0);
+            if !byte_offset.is_multiple_of(component_size) {
+                self.report(
+                    "ACCESSOR_OFFSET_ALIGNMENT",
+                    format!("/accessors/{}/byteOffset", i),
+                    format!(
+                        "Offset {} is not a multiple of the component size {}",
+                        byte_offset, component_size
+                    ),
+                );
+            }
+
+            // Sparse accessors don't need a buffer view
+            let Some(buffer_view) = accessor.buffer_view else {
+                continue;
+            };
+            if !self.check_reference(
+                format!("/accessors/{}/bufferView", i),
+                "buffer view",
+                buffer_view,
+                views.len(),
+            ) {
+                continue;
+            }
+            let view = &views.as_slice()[buffer_view];
+            // Component sizes divide 2^64, so a wrapped sum has the same
+            // alignment as the real one
+            let total_offset = view.byte_offset.wrapping_add(byte_offset);
+            if !total_offset.is_multiple_of(component_size) {
+                self.report(
+                    "ACCESSOR_TOTAL_OFFSET_ALIGNMENT",
+                    format!("/accessors/{}", i),
+                    format!(
+                        "Offset {} + {} in the buffer is not a multiple of the component size {}",
+                        view.byte_offset, byte_offset, component_size
+                    ),
+                );
+            }
+            let element_size = accessor.element_size();
+            let stride = view.stride.unwrap_or(element_size);
+            // Values from imported documents can be anything, so an overflow
+            // is reported as the accessor not fitting
+            let accessor_end = match accessor.count.checked_sub(1) {
+                None => Some(byte_offset),
+                Some(last) => stride
+                    .checked_mul(last)
+                    .and_then(|x| x.checked_add(element_size))
+                    .and_then(|x| x.checked_add(byte_offset)),
+            };
+            if accessor_end.is_none_or(|x| x > view.byte_len) {
+                self.report(
+                    "ACCESSOR_TOO_LONG",
+                    format!("/accessors/{}", i),
+                    format!(
+                        "Accessor of {} elements at offset {} doesn't fit in buffer view {} ({} bytes)",
+                        accessor.count, byte_offset, buffer_view, view.byte_len
+                    ),
+                );
+            }
+        }
+    }
+
+    fn validate_meshes(&mut self) {
+        let accessors = &self.document.buffer_writer.accessors;
+        let materials = &self.document.material_data.materials;
+        let material_count = materials.len();
+        for (i, mesh) in self.document.meshes.iter().enumerate() {
+            let target_count = mesh.primitives.first().map(|x| x.targets.len());
+            for (j, primitive) in mesh.primitives.iter().enumerate() {
+                let pointer = format!("/meshes/{}/primitives/{}", i, j);
+                let target_attributes =
+                    primitive
+                        .targets
+                        .iter()
+                        .enumerate()
+                        .flat_map(|(k, target)| {
+                            target.iter().map(move |(name, accessor)| {
+                                (format!("targets/{}/{}", k, name), accessor)
+                            })
+                        });
+                let attributes = primitive
+                    .attributes
+                    .iter()
+                    .map(|(name, accessor)| (format!("attributes/{}", name), accessor))
+                    .chain(target_attributes);
+                let mut vertex_count = None;
+                for (attribute_pointer, accessor) in attributes {
+                    if !self.check_reference(
+                        format!("{}/{}", pointer, attribute_pointer),
+                        "accessor",
+                        *accessor,
+                        accessors.len(),
+                    ) {
+                        continue;
+                    }
+                    let count = accessors.as_slice()[*accessor].count;
+                    if !self.is_vertex_aligned(*accessor) {
+                        self.report(
+                            "MESH_PRIMITIVE_ACCESSOR_UNALIGNED",
+                            format!("{}/{}", pointer, attribute_pointer),
+                            "Vertex attribute data must be aligned to 4 bytes".to_owned(),
+                        );
+                    }
+                    match vertex_count {
+                        None => vertex_count = Some(count),
+                        Some(vertex_count) if vertex_count != count => {
+                            self.report(
+                                "MESH_PRIMITIVE_UNEQUAL_ACCESSOR_COUNT",
+                                format!("{}/{}", pointer, attribute_pointer),
+                                format!(
+                                    "Attribute has {} elements but others have {}",
+                                    count, vertex_count
+                                ),
+                            );
+                        }
+                        _ => {}
+                    }
+                }
+                for (name, accessor) in &primitive.attributes {
+                    let Some(accessor) = accessors.as_slice().get(*accessor) else {
+                        continue;
+                    };
+                    if let Err((code, message)) = check_attribute_format(
+                        name,
+                        accessor.component_ty,
+                        accessor.ty,
+                        accessor.normalized,
+                    ) {
+                        self.report(code, format!("{}/attributes/{}", pointer, name), message);
+                    }
+                }
+                let element_count = match primitive.indices {
+                    Some(indices) => self
+                        .check_reference(
+                            format!("{}/indices", pointer),
+                            "accessor",
+                            indices.0,
+                            accessors.len(),
+                        )
+                        .then(|| accessors.as_slice()[indices.0].count),
+                    None => vertex_count,
+                };
+                if let Some(element_count) = element_count
+                    && !primitive.mode.is_valid_count(element_count)
+                {
+                    self.report(
+                        "MESH_PRIMITIVE_INCOMPATIBLE_MODE",
+                        format!("{}/mode", pointer),
+                        format!(
+                            "{} vertices or indices can't be drawn as {:?}",
+                            element_count, primitive.mode
+                        ),
+                    );
+                }
+                if let Some(material_index) = primitive.material
+                    && self.check_reference(
+                        format!("{}/material", pointer),
+                        "material",
+                        material_index.0,
+                        material_count,
+                    )
+                {
+                    let material = &materials.as_slice()[material_index.0];
+                    for (path, _, tex_coord) in material.texture_infos() {
+                        let attribute = format!("TEXCOORD_{}", tex_coord);
+                        if !primitive.attributes.contains_key(attribute.as_str()) {
+                            self.report(
+                                "MESH_PRIMITIVE_TOO_FEW_TEXCOORDS",
+                                format!("{}/material", pointer),
+                                format!(
+                                    "Material {} uses {} for {} but the primitive has no such attribute",
+                                    material_index.0, attribute, path
+                                ),
+                            );
+                        }
+                    }
+                }
+                if Some(primitive.targets.len()) != target_count {
+                    self.report(
+                        "MESH_PRIMITIVES_UNEQUAL_TARGETS_COUNT",
+                        format!("{}/targets", pointer),
+                        "All primitives must have the same number of morph targets".to_owned(),
+                    );
+                }
+            }
+
+            let target_count = target_count.unwrap_or(0);
+            if let Some(weights) = &mesh.weights
+                && weights.len() != target_count
+            {
+                self.report(
+                    "MESH_INVALID_WEIGHTS_COUNT",
+                    format!("/meshes/{}/weights", i),
+                    format!(
+                        "Mesh has {} weights but {} morph targets",
+                        weights.len(),
+                        target_count
+                    ),
+                );
+            }
+        }
+    }
+
+    fn validate_nodes(&mut self) {
+        let nodes = self.document.nodes.nodes.as_slice();
+        let mesh_count = self.document.meshes.len();
+        let skin_count = self.document.skins.len();
+
+        let mut parents = vec![None; nodes.len()];
+        for (i, node) in nodes.iter().enumerate() {
+            if let Some(mesh) = node.mesh {
+                self.check_reference(format!("/nodes/{}/mesh", i), "mesh", mesh.0, mesh_count);
+            }
+            if node.matrix.is_some()
+                && (node.translation.is_some() || node.rotation.is_some() || node.scale.is_some())
+            {
+                self.report(
+                    "NODE_MATRIX_TRS",
+                    format!("/nodes/{}/matrix", i),
+                    "A node can have either a matrix or any combination of translation/rotation/scale"
+                        .to_owned(),
+                );
+            }
+            if let Some(rotation) = node.rotation
+                && !rotation.is_normalized()
+            {
+                self.report(
+                    "ROTATION_NON_UNIT",
+                    format!("/nodes/{}/rotation", i),
+                    "Rotation quaternion must be normalized".to_owned(),
+                );
+            }
+            if let Some(weights) = &node.weights {
+                let target_count = self.morph_target_count(i);
+                if weights.len() != target_count {
+                    self.report(
+                        "NODE_WEIGHTS_INVALID",
+                        format!("/nodes/{}/weights", i),
+                        format!(
+                            "Node has {} weights but its mesh has {} morph targets",
+                            weights.len(),
+                            target_count
+                        ),
+                    );
+                }
+            }
+            if let Some(skin) = node.skin {
+                self.check_reference(format!("/nodes/{}/skin", i), "skin", skin.0, skin_count);
+            }
+            for (j, child) in node.children.iter().enumerate() {
+                let pointer = format!("/nodes/{}/children/{}", i, j);
+                if !self.check_reference(pointer.clone(), "node", child.0, nodes.len()) {
+                    continue;
+                }
+                match parents[child.0] {
+                    None => parents[child.0] = Some(i),
+                    Some(parent) => self.report(
+                        "NODE_PARENT_OVERRIDE",
+                        pointer,
+                        format!("Node {} already has parent {}", child.0, parent),
+                    ),
+                }
+            }
+        }
+
+        // Walk up from every node. A node in a loop will reach itself,
+        // while nodes that merely lead into a loop give up after visiting
+        // every node once.
+        for i in 0..nodes.len() {
+            let mut current = parents[i];
+            let mut steps = 0;
+            while let Some(parent) = current {
+                if parent == i {
+                    self.report(
+                        "NODE_LOOP",
+                        format!("/nodes/{}", i),
+                        "Node is its own ancestor".to_owned(),
+                    );
+                    break;
+                }
+                if steps > nodes.len() {
+                    break;
+                }
+                current = parents[parent];
+                steps += 1;
+            }
+        }
+
+        let scenes = self.document.scenes;
+        if let Some(scene) = scenes.default_scene {
+            self.check_reference("/scene".to_owned(), "scene", scene.0, scenes.len());
+        }
+        for (i, scene) in scenes.scenes.iter().enumerate() {
+            for (j, node) in scene.nodes.iter().enumerate() {
+                let pointer = format!("/scenes/{}/nodes/{}", i, j);
+                if !self.check_reference(pointer.clone(), "node", node.0, nodes.len()) {
+                    continue;
+                }
+                if let Some(parent) = parents[node.0] {
+                    self.report(
+                        "SCENE_NON_ROOT_NODE",
+                        pointer,
+                        format!("Node {} is a child of node {}", node.0, parent),
+                    );
+                }
+            }
+        }
+    }
+
+    fn validate_skins(&mut self) {
+        let accessor_count = self.document.buffer_writer.accessors.len();
+        let node_count = self.document.nodes.len();
+        let parents = self.document.nodes.parents();
+        for (i, skin) in self.document.skins.skins.iter().enumerate() {
+            if let Some(inverse_bind_matrices) = skin.inverse_bind_matrices {
+                self.check_reference(
+                    format!("/skins/{}/inverseBindMatrices", i),
+                    "accessor",
+                    inverse_bind_matrices.0,
+                    accessor_count,
+                );
+            }
+            for (j, joint) in skin.joints.iter().enumerate() {
+                self.check_reference(
+                    format!("/skins/{}/joints/{}", i, j),
+                    "node",
+                    joint.0,
+                    node_count,
+                );
+            }
+            if let Some(skeleton) = skin.skeleton {
+                self.check_reference(
+                    format!("/skins/{}/skeleton", i),
+                    "node",
+                    skeleton.0,
+                    node_count,
+                );
+            }
+
+            // https://registry.khronos.org/glTF/specs/2.0/glTF-2.0.html#joint-hierarchy
+            if !skin.joints.is_empty()
+                && skin.joints.iter().all(|x| x.0 < node_count)
+                && Nodes::common_root_with_parents(&parents, &skin.joints).is_none()
+            {
+                self.report(
+                    "SKIN_NO_COMMON_ROOT",
+                    format!("/skins/{}/joints", i),
+                    "Joints must have a common root".to_owned(),
+                );
+            }
+        }
+    }
+
+    fn validate_animations(&mut self) {
+        let accessors = self.document.buffer_writer.accessors.as_slice();
+        let node_count = self.document.nodes.len();
+        for (i, animation) in self.document.animations.animations.iter().enumerate() {
+            let sampler_count = animation.samplers.len();
+            for (j, channel) in animation.channels.iter().enumerate() {
+                // Sampler indices are local to the animation
+                let sampler_valid = self.check_reference(
+                    format!("/animations/{}/channels/{}/sampler", i, j),
+                    "sampler",
+                    channel.sampler.0,
+                    sampler_count,
+                );
+                self.check_reference(
+                    format!("/animations/{}/channels/{}/target/node", i, j),
+                    "node",
+                    channel.target.node.0,
+                    node_count,
+                );
+                if !sampler_valid {
+                    continue;
+                }
+
+                let sampler = &animation.samplers.as_slice()[channel.sampler.0];
+                let (Some(input), Some(output)) = (
+                    accessors.get(sampler.input.0),
+                    accessors.get(sampler.output.0),
+                ) else {
+                    continue;
+                };
+                let output_pointer =
+                    format!("/animations/{}/samplers/{}/output", i, channel.sampler.0);
+                let float = matches!(output.component_ty, AccessorComponentType::Float)
+                    && !output.normalized;
+                let normalized = !matches!(
+                    output.component_ty,
+                    AccessorComponentType::Float | AccessorComponentType::UnsignedInt
+                ) && output.normalized;
+                let (valid_format, values_per_keyframe) = match channel.target.path {
+                    AnimationTarget::Translation | AnimationTarget::Scale => {
+                        (matches!(output.ty, AccessorDataType::Vec3) && float, 1)
+                    }
+                    AnimationTarget::Rotation => (
+                        matches!(output.ty, AccessorDataType::Vec4) && (float || normalized),
+                        1,
+                    ),
+                    // One value per morph target for each keyframe
+                    AnimationTarget::Weights => {
+                        let target_count = self.morph_target_count(channel.target.node.0);
+                        if target_count == 0 {
+                            self.report(
+                                "ANIMATION_CHANNEL_TARGET_NODE_WEIGHTS_NO_MORPHS",
+                                format!("/animations/{}/channels/{}/target/path", i, j),
+                                "Node's mesh has no morph targets to animate".to_owned(),
+                            );
+                            continue;
+                        }
+                        (
+                            matches!(output.ty, AccessorDataType::Scalar) && (float || normalized),
+                            target_count,
+                        )
+                    }
+                };
+                if !valid_format {
+                    self.report(
+                        "ANIMATION_SAMPLER_OUTPUT_ACCESSOR_INVALID_FORMAT",
+                        output_pointer.clone(),
+                        format!(
+                            "{:?} {:?} isn't a valid output format for {:?}",
+                            output.ty, output.component_ty, channel.target.path
+                        ),
+                    );
+                }
+                let elements_per_value = match sampler.interpolation {
+                    AnimationInterpolation::Linear | AnimationInterpolation::Step => 1,
+                    // In-tangent, value and out-tangent
+                    AnimationInterpolation::CubicSpline => 3,
+                };
+                let expected_count = input.count * values_per_keyframe * elements_per_value;
+                if output.count != expected_count {
+                    self.report(
+                        "ANIMATION_SAMPLER_OUTPUT_ACCESSOR_INVALID_COUNT",
+                        output_pointer,
+                        format!(
+                            "Output accessor has {} elements but {} were expected",
+                            output.count, expected_count
+                        ),
+                    );
+                }
+            }
+            for (j, sampler) in animation.samplers.iter().enumerate() {
+                let input_valid = self.check_reference(
+                    format!("/animations/{}/samplers/{}/input", i, j),
+                    "accessor",
+                    sampler.input.0,
+                    accessors.len(),
+                );
+                self.check_reference(
+                    format!("/animations/{}/samplers/{}/output", i, j),
+                    "accessor",
+                    sampler.output.0,
+                    accessors.len(),
+                );
+                if !input_valid {
+                    continue;
+                }
+                let input = &accessors[sampler.input.0];
+                if !matches!(input.ty, AccessorDataType::Scalar)
+                    || !matches!(input.component_ty, AccessorComponentType::Float)
+                    || input.normalized
+                {
+                    self.report(
+                        "ANIMATION_SAMPLER_INPUT_ACCESSOR_INVALID_FORMAT",
+                        format!("/animations/{}/samplers/{}/input", i, j),
+                        "Input accessor must contain scalar floats".to_owned(),
+                    );
+                }
+                if matches!(sampler.interpolation, AnimationInterpolation::CubicSpline)
+                    && input.count < 2
+                {
+                    self.report(
+                        "ANIMATION_SAMPLER_INPUT_ACCESSOR_TOO_FEW_ELEMENTS",
+                        format!("/animations/{}/samplers/{}/input", i, j),
+                        "Cubic spline samplers need at least 2 keyframes".to_owned(),
+                    );
+                }
+            }
+        }
+    }
+
+    fn validate_materials(&mut self) {
+        let material_data = self.document.material_data;
+        let texture_count = material_data.textures.len();
+        for (i, material) in material_data.materials.iter().enumerate() {
+            for (path, texture, _) in material.texture_infos() {
+                self.check_reference(
+                    format!("/materials/{}/{}/index", i, path),
+                    "texture",
+                    texture.0,
+                    texture_count,
+                );
+            }
+            if let Some(alpha_cutoff) = material.alpha_cutoff {
+                if !matches!(material.alpha_mode, Some(AlphaMode::Mask)) {
+                    self.report(
+                        "MATERIAL_ALPHA_CUTOFF_INVALID_MODE",
+                        format!("/materials/{}/alphaCutoff", i),
+                        "Alpha cutoff is only supported with the MASK alpha mode".to_owned(),
+                    );
+                }
+                if alpha_cutoff < 0.0 {
+                    self.report(
+                        "VALUE_NOT_IN_RANGE",
+                        format!("/materials/{}/alphaCutoff", i),
+                        format!("Alpha cutoff {} must not be negative", alpha_cutoff),
+                    );
+                }
+            }
+            if let Some(emissive_factor) = material.emissive_factor
+                && (emissive_factor.min_element() < 0.0 || emissive_factor.max_element() > 1.0)
+            {
+                self.report(
+                    "VALUE_NOT_IN_RANGE",
+                    format!("/materials/{}/emissiveFactor", i),
+                    "Emissive factor components must be between 0 and 1".to_owned(),
+                );
+            }
+            if let Some(occlusion_texture) = &material.occlusion_texture
+                && !(0.0..=1.0).contains(&occlusion_texture.strength)
+            {
+                self.report(
+                    "VALUE_NOT_IN_RANGE",
+                    format!("/materials/{}/occlusionTexture/strength", i),
+                    "Occlusion strength must be between 0 and 1".to_owned(),
+                );
+            }
+        }
+        for (i, texture) in material_data.textures.iter().enumerate() {
+            if let Some(sampler) = texture.sampler {
+                self.check_reference(
+                    format!("/textures/{}/sampler", i),
+                    "sampler",
+                    sampler.0,
+                    material_data.samplers.len(),
+                );
+            }
+            self.check_reference(
+                format!("/textures/{}/source", i),
+                "image",
+                texture.source.0,
+                material_data.images.len(),
+            );
+        }
+        let view_count = self.document.buffer_writer.views.len();
+        for (i, image) in material_data.images.iter().enumerate() {
+            let pointer = format!("/images/{}", i);
+            match (&image.uri, image.buffer_view) {
+                (Some(_), None) => {}
+                (None, Some(buffer_view)) => {
+                    self.check_reference(
+                        format!("{}/bufferView", pointer),
+                        "buffer view",
+                        buffer_view.0,
+                        view_count,
+                    );
+                    if image.mime_type.is_none() {
+                        self.report(
+                            "PROPERTY_DEPENDENCY_MISSING",
+                            pointer,
+                            "Images stored in a buffer view must have a mimeType".to_owned(),
+                        );
+                    }
+                }
+                (Some(_), Some(_)) => self.report(
+                    "ONE_OF_MISMATCH",
+                    pointer,
+                    "Images can't have both a uri and a bufferView".to_owned(),
+                ),
+                (None, None) => self.report(
+                    "ONE_OF_MISSING",
+                    pointer,
+                    "Images must have either a uri or a bufferView".to_owned(),
+                ),
+            }
+        }
+    }
+}
+
+pub(crate) fn validate(document: &GltfDocument) -> Vec<Diagnostic> {
+    let mut validator = Validator {
+        document,
+        diagnostics: Vec::new(),
+    };
+    validator.validate_buffer_views();
+    validator.validate_accessors();
+    validator.validate_meshes();
+    validator.validate_nodes();
+    validator.validate_skins();
+    validator.validate_animations();
+    validator.validate_materials();
+    validator.diagnostics
+}
@@ -0,0 +1,485 @@
+use std::{borrow::Cow, ops::Range};
+
+use crate::{
+    Vertex,
+    buffer::{
+        AccessorComponentType, AccessorDataType, AccessorIndex, BufferTypeEx, BufferTypeMinMax,
+        BufferViewTarget, BufferWriter, MinMax,
+    },
+    error::{Error, Result},
+    transform::{ConvertAttribute, CoordinateSystem},
+};
+
+/// Vertex data that can be written as a set of named attributes.
+pub trait VertexSource {
+    fn vertex_count(&self) -> usize;
+    /// Writes the vertices in `range`. `range` is always within `vertex_count`.
+    fn write_attributes(
+        &self,
+        writer: &mut BufferWriter,
+        range: Range<usize>,
+    ) -> Vec<(Cow<'static, str>, usize)>;
+    /// Builds a new set of vertices from the vertices at `indices`.
+    fn gather(&self, indices: &[usize]) -> Self
+    where
+        Self: Sized;
+    /// Converts the vertices from `system` to glTF's coordinate system.
+    fn convert_coordinates(&mut self, system: &CoordinateSystem);
+}
+
+impl<T: Vertex + Clone> VertexSource for Vec<T> {
+    fn vertex_count(&self) -> usize {
+        self.len()
+    }
+
+    fn write_attributes(
+        &self,
+        writer: &mut BufferWriter,
+        range: Range<usize>,
+    ) -> Vec<(Cow<'static, str>, usize)> {
+        T::write_slices(writer, &self[range])
+            .into_iter()
+            .map(|(name, accessor)| (Cow::Borrowed(name), accessor))
+            .collect()
+    }
+
+    fn gather(&self, indices: &[usize]) -> Self {
+        indices.iter().map(|x| self[*x].clone()).collect()
+    }
+
+    fn convert_coordinates(&mut self, system: &CoordinateSystem) {
+        T::convert_coordinates(self, system);
+    }
+}
+
+trait AttributeData {
+    fn len(&self) -> usize;
+    fn component_ty(&self) -> AccessorComponentType;
+    fn ty(&self) -> AccessorDataType;
+    fn write(&self, writer: &mut BufferWriter, range: Range<usize>) -> AccessorIndex;
+    fn gather(&self, indices: &[usize]) -> Box<dyn AttributeData>;
+    fn convert_coordinates(&mut self, name: &str, system: &CoordinateSystem);
+}
+
+impl<T: BufferTypeMinMax + ConvertAttribute + Copy + 'static> AttributeData for Vec<T> {
+    fn len(&self) -> usize {
+        self.len()
+    }
+
+    fn component_ty(&self) -> AccessorComponentType {
+        T::COMPONENT_TY
+    }
+
+    fn ty(&self) -> AccessorDataType {
+        T::TY
+    }
+
+    fn write(&self, writer: &mut BufferWriter, range: Range<usize>) -> AccessorIndex {
+        let data = &self[range];
+        let element_size = T::COMPONENT_TY.size() * T::TY.component_count();
+        if element_size.is_multiple_of(4) {
+            return writer
+                .create_view_and_accessor_with_min_max(data, Some(BufferViewTarget::ArrayBuffer))
+                .accessor;
+        }
+
+        // Vertex attribute elements must be aligned to 4 bytes, so smaller
+        // elements (e.g. a u16 `_ID`) are padded out
+        let stride = element_size.next_multiple_of(4);
+        let mut bytes = vec![0u8; stride * data.len()];
+        for (value, element) in data.iter().zip(bytes.chunks_exact_mut(stride)) {
+            let value = value.to_bytes();
+            element[..value.len()].copy_from_slice(&value);
+        }
+        let view = writer.create_strided_view(&bytes, stride, Some(BufferViewTarget::ArrayBuffer));
+        let (min, max) = T::find_min_max(data);
+        writer.create_accessor_with_min_max(view, 0, data.len(), MinMax { min, max })
+    }
+
+    fn gather(&self, indices: &[usize]) -> Box<dyn AttributeData> {
+        Box::new(indices.iter().map(|x| self[*x]).collect::<Vec<_>>())
+    }
+
+    fn convert_coordinates(&mut self, name: &str, system: &CoordinateSystem) {
+        for value in self {
+            value.convert_attribute(name, system);
+        }
+    }
+}
+
+struct VertexAttribute {
+    name: Cow<'static, str>,
+    data: Box<dyn AttributeData>,
+    normalized: bool,
+}
+
+/// Vertex data whose attributes are only known at runtime. Each attribute
+/// is written to its own buffer view.
+#[derive(Default)]
+pub struct VertexBuffer {
+    vertex_count: usize,
+    attributes: Vec<VertexAttribute>,
+}
+
+impl VertexBuffer {
+    pub fn new(vertex_count: usize) -> Self {
+        Self {
+            vertex_count,
+            attributes: Vec::new(),
+        }
+    }
+
+    pub fn add_attribute<T: BufferTypeMinMax + ConvertAttribute + Copy + 'static>(
+        &mut self,
+        name: impl Into<Cow<'static, str>>,
+        data: Vec<T>,
+    ) -> Result<()> {
+        self.add(name.into(), Box::new(data), false)
+    }
+
+    /// Adds an attribute whose integer components are read as values in [0, 1].
+    pub fn add_normalized_attribute<T: BufferTypeMinMax + ConvertAttribute + Copy + 'static>(
+        &mut self,
+        name: impl Into<Cow<'static, str>>,
+        data: Vec<T>,
+    ) -> Result<()> {
+        self.add(name.into(), Box::new(data), true)
+    }
+
+    fn add(
+        &mut self,
+        name: Cow<'static, str>,
+        data: Box<dyn AttributeData>,
+        normalized: bool,
+    ) -> Result<()> {
+        if data.len() != self.vertex_count {
+            return Err(Error::SpecViolation(format!(
+                "Attribute {} has {} elements but there are {} vertices",
+                name,
+                data.len(),
+                self.vertex_count
+            )));
+        }
+        if self.attributes.iter().any(|x| x.name == name) {
+            return Err(Error::SpecViolation(format!(
+                "Attribute {} was added more than once",
+                name
+            )));
+        }
+        check_attribute_format(&name, data.component_ty(), data.ty(), normalized)
+            .map_err(|(_, message)| Error::SpecViolation(message))?;
+        self.attributes.push(VertexAttribute {
+            name,
+            data,
+            normalized,
+        });
+        Ok(())
+    }
+
+    /// Adds JOINTS_n/WEIGHTS_n sets from arbitrary per-vertex
+    /// (joint, weight) lists. Each list is sorted by weight, pruned to the
+    /// `max_influences` largest non-zero weights and normalized. A set is
+    /// added for every four influences the busiest vertex needs. Joint
+    /// indices are written as u8 when `joint_count` allows it, u16 otherwise.
+    pub fn add_skin_weights(
+        &mut self,
+        influences: &[Vec<(usize, f32)>],
+        joint_count: usize,
+        max_influences: usize,
+    ) -> Result<()> {
+        if joint_count > u16::MAX as usize + 1 {
+            return Err(Error::SpecViolation(format!(
+                "Skins can have at most {} joints, not {}",
+                u16::MAX as usize + 1,
+                joint_count
+            )));
+        }
+        if influences.len() != self.vertex_count {
+            return Err(Error::SpecViolation(format!(
+                "There are {} skin weights but {} vertices",
+                influences.len(),
+                self.vertex_count
+            )));
+        }
+
+        let influences = influences
+            .iter()
+            .enumerate()
+            .map(|(i, x)| normalize_influences(i, x, joint_count, max_influences))
+            .collect::<Result<Vec<_>>>()?;
+        let set_count = influences
+            .iter()
+            .map(|x| x.len().div_ceil(4))
+            .max()
+            .unwrap_or(0);
+
+        for set in 0..set_count {
+            let mut joints = Vec::with_capacity(influences.len());
+            let mut weights = Vec::with_capacity(influences.len());
+            for vertex in &influences {
+                // Unused slots are joint 0 with a weight of 0
+                let mut vertex_joints = [0u16; 4];
+                let mut vertex_weights = [0.0; 4];
+                for (i, (joint, weight)) in vertex.iter().skip(set * 4).take(4).enumerate() {
+                    vertex_joints[i] = *joint as u16;
+                    vertex_weights[i] = *weight;
+                }
+                joints.push(vertex_joints);
+                weights.push(vertex_weights);
+            }
+
+            let joints_name = format!("JOINTS_{}", set);
+            if joint_count <= u8::MAX as usize + 1 {
+                let joints: Vec<[u8; 4]> = joints.iter().map(|x| x.map(|x| x as u8)).collect();
+                self.add_attribute(joints_name, joints)?;
+            } else {
+                self.add_attribute(joints_name, joints)?;
+            }
+            self.add_attribute(format!("WEIGHTS_{}", set), weights)?;
+        }
+        Ok(())
+    }
+}
+
+// Merges duplicate joints, drops zero weights, keeps the largest
+// `max_influences` and scales the rest to sum to 1.
+fn normalize_influences(
+    vertex: usize,
+    influences: &[(usize, f32)],
+    joint_count: usize,
+    max_influences: usize,
+) -> Result<Vec<(usize, f32)>> {
+    let mut merged: Vec<(usize, f32)> = Vec::with_capacity(influences.len());
+    for (joint, weight) in influences {
+        if *joint >= joint_count {
+            return Err(Error::InvalidIndex {
+                kind: "joint",
+                index: *joint,
+                len: joint_count,
+            });
+        }
+        if !weight.is_finite() || *weight < 0.0 {
+            return Err(Error::SpecViolation(format!(
+                "Vertex {} has an invalid weight {} for joint {}",
+                vertex, weight, joint
+            )));
+        }
+        match merged.iter_mut().find(|x| x.0 == *joint) {
+            Some(existing) => existing.1 += weight,
+            None => merged.push((*joint, *weight)),
+        }
+    }
+    merged.retain(|x| x.1 > 0.0);
+    merged.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+    merged.truncate(max_influences);
+
+    let sum: f32 = merged.iter().map(|x| x.1).sum();
+    if merged.is_empty() || sum <= 0.0 {
+        return Err(Error::SpecViolation(format!(
+            "Vertex {} has no joint influences",
+            vertex
+        )));
+    }
+    for influence in &mut merged {
+        influence.1 /= sum;
+    }
+    // Put the rounding error on the largest weight so the sum is as close
+    // to 1 as f32 allows
+    let sum: f32 = merged.iter().map(|x| x.1).sum();
+    merged[0].1 += 1.0 - sum;
+    Ok(merged)
+}
+
+impl VertexSource for VertexBuffer {
+    fn vertex_count(&self) -> usize {
+        self.vertex_count
+    }
+
+    fn write_attributes(
+        &self,
+        writer: &mut BufferWriter,
+        range: Range<usize>,
+    ) -> Vec<(Cow<'static, str>, usize)> {
+        let mut attributes = Vec::with_capacity(self.attributes.len());
+        for attribute in &self.attributes {
+            let accessor = attribute.data.write(writer, range.clone());
+            if attribute.normalized {
+                writer.set_normalized(accessor, true);
+            }
+            attributes.push((attribute.name.clone(), accessor.0));
+        }
+        attributes
+    }
+
+    fn gather(&self, indices: &[usize]) -> Self {
+        Self {
+            vertex_count: indices.len(),
+            attributes: self
+                .attributes
+                .iter()
+                .map(|x| VertexAttribute {
+                    name: x.name.clone(),
+                    data: x.data.gather(indices),
+                    normalized: x.normalized,
+                })
+                .collect(),
+        }
+    }
+
+    fn convert_coordinates(&mut self, system: &CoordinateSystem) {
+        for attribute in &mut self.attributes {
+            attribute.data.convert_coordinates(&attribute.name, system);
+        }
+    }
+}
+
+// https://registry.khronos.org/glTF/specs/2.0/glTF-2.0.html#meshes-overview
+// Errors are returned as (validation code, message).
+pub(crate) fn check_attribute_format(
+    name: &str,
+    component_ty: AccessorComponentType,
+    ty: AccessorDataType,
+    normalized: bool,
+) -> std::result::Result<(), (&'static str, String)> {
+    use AccessorComponentType::{Float, UnsignedByte, UnsignedInt, UnsignedShort};
+    use AccessorDataType::{Vec2, Vec3, Vec4};
+
+    // Application specific attributes can use any format except unsigned int
+    if name.starts_with('_') {
+        return if matches!(component_ty, UnsignedInt) {
+            Err((
+                "MESH_PRIMITIVE_ATTRIBUTES_ACCESSOR_INVALID_FORMAT",
+                format!("Attribute {} can't use unsigned int components", name),
+            ))
+        } else {
+            Ok(())
+        };
+    }
+
+    let float = matches!(component_ty, Float) && !normalized;
+    let unsigned_normalized = matches!(component_ty, UnsignedByte | UnsignedShort) && normalized;
+    let semantic = match name.split_once('_') {
+        Some((semantic, set)) if !set.is_empty() && set.bytes().all(|x| x.is_ascii_digit()) => {
+            semantic
+        }
+        _ => name,
+    };
+    let has_set = semantic.len() != name.len();
+    let valid = match (semantic, has_set) {
+        ("POSITION" | "NORMAL", false) => matches!(ty, Vec3) && float,
+        ("TANGENT", false) => matches!(ty, Vec4) && float,
+        ("TEXCOORD", true) => matches!(ty, Vec2) && (float || unsigned_normalized),
+        ("COLOR", true) => matches!(ty, Vec3 | Vec4) && (float || unsigned_normalized),
+        ("JOINTS", true) => {
+            matches!(ty, Vec4)
+                && matches!(component_ty, UnsignedByte | UnsignedShort)
+                && !normalized
+        }
+        ("WEIGHTS", true) => matches!(ty, Vec4) && (float || unsigned_normalized),
+        _ => {
+            return Err((
+                "MESH_PRIMITIVE_INVALID_ATTRIBUTE",
+                format!("Attribute {} isn't a valid semantic name", name),
+            ));
+        }
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err((
+            "MESH_PRIMITIVE_ATTRIBUTES_ACCESSOR_INVALID_FORMAT",
+            format!(
+                "Attribute {} can't use {:?} {:?}{} components",
+                name,
+                ty,
+                component_ty,
+                if normalized { " normalized" } else { "" }
+            ),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_merges_duplicate_joints() {
+        let influences = [(2, 0.25), (1, 0.5), (2, 0.25), (3, 0.0)];
+        let merged = normalize_influences(0, &influences, 4, 4).unwrap();
+        assert_eq!(merged, [(1, 0.5), (2, 0.5)]);
+    }
+
+    #[test]
+    fn normalize_truncates_to_max_influences() {
+        let influences = [(0, 0.1), (1, 0.4), (2, 0.2), (3, 0.3)];
+        let merged = normalize_influences(0, &influences, 4, 2).unwrap();
+        let joints: Vec<usize> = merged.iter().map(|x| x.0).collect();
+        assert_eq!(joints, [1, 3]);
+        assert!((merged[0].1 - 4.0 / 7.0).abs() < 1e-6);
+        assert!((merged[1].1 - 3.0 / 7.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn normalize_puts_rounding_error_on_largest_weight() {
+        let influences = [(0, 0.1), (1, 0.7), (2, 0.1), (3, 0.1)];
+        let sum: f32 = influences.iter().map(|x| x.1).sum();
+        let merged = normalize_influences(0, &influences, 4, 4).unwrap();
+        assert_eq!(merged[0].0, 1);
+        for (i, (joint, weight)) in merged.iter().enumerate().skip(1) {
+            assert_eq!(*weight, influences[*joint].1 / sum, "influence {}", i);
+        }
+        let total: f32 = merged.iter().map(|x| x.1).sum();
+        assert_eq!(total, 1.0);
+    }
+
+    #[test]
+    fn normalize_rejects_invalid_influences() {
+        assert!(normalize_influences(0, &[(4, 1.0)], 4, 4).is_err());
+        assert!(normalize_influences(0, &[(0, -1.0)], 4, 4).is_err());
+        assert!(normalize_influences(0, &[(0, f32::NAN)], 4, 4).is_err());
+        assert!(normalize_influences(0, &[(0, 0.0)], 4, 4).is_err());
+    }
+
+    fn joints_component_ty(joint_count: usize) -> AccessorComponentType {
+        let mut vertices = VertexBuffer::new(1);
+        let influences = [vec![(joint_count - 1, 1.0)]];
+        vertices
+            .add_skin_weights(&influences, joint_count, 4)
+            .unwrap();
+        let joints = vertices
+            .attributes
+            .iter()
+            .find(|x| x.name == "JOINTS_0")
+            .unwrap();
+        joints.data.component_ty()
+    }
+
+    #[test]
+    fn skin_joints_component_type() {
+        assert!(matches!(
+            joints_component_ty(256),
+            AccessorComponentType::UnsignedByte
+        ));
+        assert!(matches!(
+            joints_component_ty(257),
+            AccessorComponentType::UnsignedShort
+        ));
+    }
+
+    #[test]
+    fn skin_weights_sets() {
+        let mut vertices = VertexBuffer::new(2);
+        let influences = [(0..6).map(|x| (x, 1.0)).collect(), vec![(0, 1.0)]];
+        vertices.add_skin_weights(&influences, 6, 8).unwrap();
+        let names: Vec<&str> = vertices.attributes.iter().map(|x| &*x.name).collect();
+        assert_eq!(names, ["JOINTS_0", "WEIGHTS_0", "JOINTS_1", "WEIGHTS_1"]);
+
+        assert!(vertices.add_skin_weights(&influences[..1], 6, 8).is_err());
+        assert!(
+            VertexBuffer::new(1)
+                .add_skin_weights(&[vec![(0, 1.0)]], u16::MAX as usize + 2, 4)
+                .is_err()
+        );
+    }
+}